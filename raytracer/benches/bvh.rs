@@ -0,0 +1,111 @@
+// BVH 的建树与求交性能
+// 同样的物体与光线 分别用三种建树方法比较
+// 运行 cargo bench --bench bvh
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use raytracer::bvh_node::{self, BVHNode};
+use raytracer::cli::{BvhBuilder, SamplerType};
+use raytracer::hittable::hittable_list::HittableList;
+use raytracer::hittable::sphere::Sphere;
+use raytracer::hittable::Hittable;
+use raytracer::material::Lambertian;
+use raytracer::obj_file;
+use raytracer::ray::Ray;
+use raytracer::sampler;
+use raytracer::texture::SolidColor;
+use raytracer::vec3::{Color, Vec3};
+
+const BUILDERS: [(&str, BvhBuilder); 3] = [
+    ("linear", BvhBuilder::Linear),
+    ("sah", BvhBuilder::Sah),
+    ("median", BvhBuilder::Median),
+];
+
+const RAYS: usize = 4096;
+
+fn material() -> Lambertian<SolidColor> {
+    Lambertian::new(SolidColor::new(Color::new(0.5, 0.5, 0.5)))
+}
+
+// 散布在立方体中的小球
+fn spheres() -> HittableList {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut list = HittableList::new();
+    for _ in 0..10000 {
+        let center = Vec3::new(
+            rng.gen_range(-50.0..50.0),
+            rng.gen_range(-50.0..50.0),
+            rng.gen_range(-50.0..50.0),
+        );
+        list.add(Box::new(Sphere::new(
+            center,
+            rng.gen_range(0.2..1.0),
+            material(),
+        )));
+    }
+    list
+}
+
+fn cat() -> HittableList {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/obj/cat.obj");
+    HittableList {
+        objects: obj_file::load(path.to_string(), material(), 1.0),
+    }
+}
+
+// 从包围盒外的球面射向盒内随机一点的光线
+fn rays(world: &dyn Hittable) -> Vec<Ray> {
+    let bounding_box = world.bounding_box(0.0, 1.0).unwrap();
+    let center = (bounding_box.min + bounding_box.max) * 0.5;
+    let size = bounding_box.max - bounding_box.min;
+    let radius = size.length();
+    let mut rng = StdRng::seed_from_u64(2);
+    (0..RAYS)
+        .map(|_| {
+            let origin = center + Vec3::random_unit_vector(&mut rng) * radius;
+            let target = Vec3::new(
+                bounding_box.min.x + rng.gen::<f64>() * size.x,
+                bounding_box.min.y + rng.gen::<f64>() * size.y,
+                bounding_box.min.z + rng.gen::<f64>() * size.z,
+            );
+            Ray::new(origin, target - origin, 0.0)
+        })
+        .collect()
+}
+
+fn bench_scene(c: &mut Criterion, name: &str, scene: fn() -> HittableList) {
+    let mut group = c.benchmark_group(name);
+    for (label, builder) in BUILDERS {
+        // 网格模型加载时就按所选方法建树
+        bvh_node::set_builder(builder);
+        group.bench_function(BenchmarkId::new("build", label), |b| {
+            b.iter_with_large_drop(|| BVHNode::create(scene(), 0.0, 1.0))
+        });
+        let world = BVHNode::create(scene(), 0.0, 1.0);
+        let rays = rays(world.as_ref());
+        // 场景中没有介质 求交不会用到随机数
+        let mut sampler = sampler::create(SamplerType::Independent, 0, 0, 0, 1);
+        group.bench_function(BenchmarkId::new("hit", label), |b| {
+            b.iter(|| {
+                rays.iter()
+                    .filter(|&&ray| {
+                        world
+                            .hit(ray, 0.001, f64::INFINITY, sampler.as_mut())
+                            .is_some()
+                    })
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bvh(c: &mut Criterion) {
+    bench_scene(c, "spheres", spheres);
+    bench_scene(c, "cat", cat);
+}
+
+criterion_group!(benches, bvh);
+criterion_main!(benches);
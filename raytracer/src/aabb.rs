@@ -1,5 +1,6 @@
 // 轴对齐边界框 Axis-Aligned Bounding Boxes
 
+use crate::stats::{self, Counter};
 use crate::vec3::Vec3;
 use std::mem;
 
@@ -31,17 +32,40 @@ impl Aabb {
         }
     }
 
+    // 表面积 光线穿过盒子的概率与之成正比
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
     pub fn hit(&self, ray: crate::ray::Ray, t_min: f64, t_max: f64) -> bool {
+        let inv_direction = Vec3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        self.hit_inv(ray.origin, inv_direction, t_min, t_max)
+    }
+
+    // 方向的倒数由调用者预先算好 遍历时同一条光线测试许多盒子
+    pub fn hit_inv(&self, origin: Vec3, inv_direction: Vec3, t_min: f64, t_max: f64) -> bool {
+        stats::count(Counter::AabbTests);
+        let mut t_min = t_min;
+        let mut t_max = t_max;
         for i in 0..3 {
-            let invd = 1.0 / ray.direction[i];
-            let mut t0 = (self.min[i] - ray.origin[i]) * invd;
-            let mut t1 = (self.max[i] - ray.origin[i]) * invd;
+            let invd = inv_direction[i];
+            let mut t0 = (self.min[i] - origin[i]) * invd;
+            let mut t1 = (self.max[i] - origin[i]) * invd;
             if invd < 0.0 {
                 mem::swap(&mut t0, &mut t1);
             }
-            let tmin = t0.max(t_min);
-            let tmax = t1.min(t_max);
-            if tmax <= tmin {
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max <= t_min {
                 return false;
             }
         }
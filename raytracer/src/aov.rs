@@ -0,0 +1,221 @@
+// 任意输出变量 (AOV)
+// 相机光线第一次碰到物体时的几何与材质信息 供合成与降噪使用
+// 与渲染使用同样的采样序列 前几个采样的光线与渲染时完全相同
+
+use crate::camera::Camera;
+use crate::cli::{Aov, ToneMapper};
+use crate::color;
+use crate::film::Film;
+use crate::hittable::Hittable;
+use crate::integrator::Integrator;
+use crate::output::{self, OutputError};
+use crate::render::RenderSettings;
+use crate::sampler;
+use crate::tonemap::ToneMapping;
+use crate::vec3::Vec3;
+use rayon::prelude::*;
+use std::path::Path;
+
+const COUNT: usize = 7;
+
+impl Aov {
+    // 文件名与 EXR 图层名
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Albedo => "albedo",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+
+    // EXR 图层中的通道 依次取胶片的前几个通道
+    fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Uv => &["U", "V"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+        }
+    }
+}
+
+// 一个像素的全部输出变量 按 Aov 的顺序存放
+// 法线与反照率按全部采样平均 没有碰到物体的采样记为 0
+// 深度 位置与纹理坐标只对碰到物体的采样平均 编号取第 0 个采样 不做平均
+fn first_hit(
+    integrator: &Integrator,
+    camera: &Camera,
+    settings: &RenderSettings,
+    samples: u32,
+    i: u32,
+    y: u32,
+) -> [Vec3; COUNT] {
+    let j = settings.height - 1 - y;
+    let pixel = y as u64 * settings.width as u64 + i as u64;
+    let mut sum = [Vec3::zero(); COUNT];
+    let mut hits = 0;
+    for n in 0..samples {
+        let mut sampler = sampler::create(
+            settings.sampler,
+            settings.seed,
+            pixel,
+            n,
+            settings.samples_per_pixel,
+        );
+        let (du, dv) = sampler.get_2d();
+        let u = (i as f64 + du) / (settings.width - 1) as f64;
+        let v = (j as f64 + dv) / (settings.height - 1) as f64;
+        let ray = camera.get_ray(u, v, sampler.as_mut());
+        let hit_record = match integrator
+            .world
+            .hit(ray, 0.0001, f64::INFINITY, sampler.as_mut())
+        {
+            Some(hit_record) => hit_record,
+            None => continue,
+        };
+        hits += 1;
+        let depth = hit_record.t * ray.direction.length();
+        let albedo = color::from_working(hit_record.material.albedo(&hit_record));
+        sum[Aov::Normal as usize] += hit_record.normal;
+        sum[Aov::Depth as usize] += Vec3::new(depth, depth, depth);
+        sum[Aov::Position as usize] += hit_record.point;
+        sum[Aov::Albedo as usize] += albedo;
+        sum[Aov::Uv as usize] += Vec3::new(hit_record.u, hit_record.v, 0.0);
+        if n == 0 {
+            let object = hit_record.object_id as f64;
+            let material = hit_record.material.id() as f64;
+            sum[Aov::ObjectId as usize] = Vec3::new(object, object, object);
+            sum[Aov::MaterialId as usize] = Vec3::new(material, material, material);
+        }
+    }
+    for aov in [Aov::Normal, Aov::Albedo] {
+        sum[aov as usize] /= samples as f64;
+    }
+    if hits > 0 {
+        for aov in [Aov::Depth, Aov::Position, Aov::Uv] {
+            sum[aov as usize] /= hits as f64;
+        }
+    }
+    sum
+}
+
+// 每个像素取 samples 个采样 最多与渲染的采样数相同
+pub fn render(
+    integrator: &Integrator,
+    camera: &Camera,
+    settings: &RenderSettings,
+    aovs: &[Aov],
+    samples: u32,
+) -> Vec<(Aov, Film)> {
+    let samples = samples.clamp(1, settings.samples_per_pixel.max(1));
+    let rows: Vec<Vec<[Vec3; COUNT]>> = (0..settings.height)
+        .into_par_iter()
+        .map(|y| {
+            (0..settings.width)
+                .map(|i| first_hit(integrator, camera, settings, samples, i, y))
+                .collect()
+        })
+        .collect();
+    aovs.iter()
+        .map(|&aov| {
+            let mut film = Film::new(settings.width, settings.height);
+            for (y, row) in rows.iter().enumerate() {
+                for (x, values) in row.iter().enumerate() {
+                    film.set(x as u32, y as u32, values[aov as usize]);
+                }
+            }
+            (aov, film)
+        })
+        .collect()
+}
+
+// 图像的 RGB 通道与各输出变量的图层写入同一个 EXR 文件
+pub fn write_layers(image: &Film, aovs: &[(Aov, Film)], path: &Path) -> Result<(), OutputError> {
+    let mut channels = vec![];
+    for (c, name) in ["R", "G", "B"].iter().enumerate() {
+        channels.push((name.to_string(), image.channel(c)));
+    }
+    for (aov, film) in aovs {
+        for (c, name) in aov.channels().iter().enumerate() {
+            channels.push((format!("{}.{}", aov.name(), name), film.channel(c)));
+        }
+    }
+    let channels: Vec<(&str, &[f32])> = channels
+        .iter()
+        .map(|(name, data)| (name.as_str(), data.as_slice()))
+        .collect();
+    output::write_exr(path, image.width, image.height, &channels, &image.metadata)
+}
+
+// 线性格式保存原始数值 8 位格式换成便于查看的颜色
+pub fn write_image(aov: Aov, film: &Film, path: &Path) -> Result<(), OutputError> {
+    let plain = ToneMapping::new(0.0, None, ToneMapper::Clamp);
+    if output::is_linear(path) {
+        output::write(film, path, &plain)
+    } else {
+        output::write(&visualize(aov, film), path, &plain)
+    }
+}
+
+// 映射到 [0, 1] 的显示值 再存为线性值
+fn visualize(aov: Aov, film: &Film) -> Film {
+    let mut result = Film::new(film.width, film.height);
+    if aov == Aov::Albedo {
+        result.pixels = film.pixels.clone();
+        return result;
+    }
+    // 深度与位置按图中的范围缩放 深度不计没有碰到物体的像素
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for p in &film.pixels {
+        if aov == Aov::Depth && p[0] <= 0.0 {
+            continue;
+        }
+        for c in 0..3 {
+            min[c] = min[c].min(p[c]);
+            max[c] = max[c].max(p[c]);
+        }
+    }
+    let scale = |v: f32, c: usize| {
+        if max[c] > min[c] {
+            (v - min[c]) / (max[c] - min[c])
+        } else {
+            0.0
+        }
+    };
+    for (out, p) in result.pixels.iter_mut().zip(&film.pixels) {
+        let display = match aov {
+            Aov::Normal => [p[0] * 0.5 + 0.5, p[1] * 0.5 + 0.5, p[2] * 0.5 + 0.5],
+            // 近处亮 没有碰到物体的像素为黑色
+            Aov::Depth => {
+                let d = if p[0] > 0.0 {
+                    1.0 - scale(p[0], 0)
+                } else {
+                    0.0
+                };
+                [d, d, d]
+            }
+            Aov::Position => [scale(p[0], 0), scale(p[1], 1), scale(p[2], 2)],
+            Aov::Uv => [p[0], p[1], 0.0],
+            // 每个编号一种随机颜色 0 为黑色
+            _ => {
+                let id = p[0] as u64;
+                if id == 0 {
+                    [0.0; 3]
+                } else {
+                    let h = sampler::mix(id);
+                    [h, h >> 8, h >> 16].map(|v| (v & 0xff) as f32 / 255.0)
+                }
+            }
+        };
+        for (v, d) in out.iter_mut().zip(display) {
+            *v = color::srgb_eotf(d.clamp(0.0, 1.0) as f64) as f32;
+        }
+    }
+    result
+}
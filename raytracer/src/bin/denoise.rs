@@ -0,0 +1,109 @@
+// 对保存的线性图像降噪
+// 以 --aov albedo,normal 渲染到 .exr 时 反照率与法线作为图层保存在同一个文件里
+// 其余情况用 --albedo 与 --normal 给出单独的 .exr 或 .pfm
+
+use clap::Parser;
+use console::style;
+use raytracer::cli::{parse_denoise_iterations, parse_denoise_strength, ToneMapper};
+use raytracer::film::Film;
+use raytracer::output::{self, OutputError};
+use raytracer::tonemap::ToneMapping;
+use std::path::PathBuf;
+use std::process::exit;
+
+#[derive(Parser)]
+#[clap(
+    name = "denoise",
+    about = "Denoise a linear render guided by its albedo and normal buffers"
+)]
+struct Args {
+    /// Linear .exr or .pfm render
+    input: PathBuf,
+
+    /// Albedo image, defaults to the albedo layer of an .exr input
+    #[clap(long)]
+    albedo: Option<PathBuf>,
+
+    /// Normal image, defaults to the normal layer of an .exr input
+    #[clap(long)]
+    normal: Option<PathBuf>,
+
+    /// Output image path, .exr, .hdr and .pfm keep linear radiance, other formats are tone mapped
+    #[clap(short, long)]
+    output: PathBuf,
+
+    /// Rounds of the filter, each one reaches twice as far
+    #[clap(long, default_value_t = 5, value_parser = parse_denoise_iterations)]
+    iterations: u32,
+
+    /// How strongly the filter smooths across color differences, must be positive
+    #[clap(long, default_value_t = 1.0, value_parser = parse_denoise_strength)]
+    strength: f32,
+
+    /// Exposure in stops applied before tone mapping
+    #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    exposure: f64,
+
+    /// Curve that maps linear radiance to the displayable range
+    #[clap(long, value_enum, default_value_t = ToneMapper::Clamp)]
+    tonemap: ToneMapper,
+}
+
+// 图像 反照率 法线
+fn load(args: &Args) -> Result<(Film, Film, Film), OutputError> {
+    let exr = if output::extension(&args.input) == "exr" {
+        Some(output::read_exr(&args.input)?)
+    } else {
+        None
+    };
+    let beauty = match &exr {
+        Some(exr) => exr.film(["R", "G", "B"])?,
+        None => output::read(&args.input)?,
+    };
+    let guide = |file: &Option<PathBuf>, layer: [&str; 3]| match (file, &exr) {
+        (Some(file), _) => output::read(file),
+        (None, Some(exr)) => exr.film(layer),
+        (None, None) => Err(OutputError::Invalid(format!(
+            "--{} is required unless the input is an .exr with that layer",
+            layer[0].split('.').next().unwrap()
+        ))),
+    };
+    let albedo = guide(&args.albedo, ["albedo.R", "albedo.G", "albedo.B"])?;
+    let normal = guide(&args.normal, ["normal.X", "normal.Y", "normal.Z"])?;
+    for film in [&albedo, &normal] {
+        if (film.width, film.height) != (beauty.width, beauty.height) {
+            return Err(OutputError::Invalid(
+                "albedo and normal must be as large as the image".to_string(),
+            ));
+        }
+    }
+    Ok((beauty, albedo, normal))
+}
+
+fn main() {
+    let args = Args::parse();
+    let (beauty, albedo, normal) = match load(&args) {
+        Ok(buffers) => buffers,
+        Err(err) => {
+            println!(
+                "{} \"{}\": {}",
+                style("Cannot read image").red(),
+                args.input.display(),
+                err
+            );
+            exit(1);
+        }
+    };
+    let film =
+        raytracer::denoise::denoise(&beauty, &albedo, &normal, args.iterations, args.strength);
+
+    println!(
+        "Ouput image as \"{}\"",
+        style(args.output.to_str().unwrap()).yellow()
+    );
+    let tone = ToneMapping::new(args.exposure, None, args.tonemap);
+    if let Err(err) = output::write(&film, &args.output, &tone) {
+        println!("{} {}", style("Outputting image fails.").red(), err);
+        exit(1);
+    }
+}
@@ -1,9 +1,21 @@
 // BVH节点类
+// 两种建树方法 按表面积启发式 (SAH) 分桶划分 或沿随机轴在包围盒最小值的中位数处对半划分
+// SAH 建的树默认展开成连续的数组 见 linear_bvh.rs
+// 物体很多时 SAH 的划分与子树在线程池中并行建造
 
 use crate::aabb::Aabb;
+use crate::cli::BvhBuilder;
 use crate::hittable::*;
 use crate::hittable_list::HittableList;
-use rand::Rng;
+use crate::linear_bvh::LinearBVH;
+use crate::sampler::Sampler;
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 pub struct BVHNode {
     left: Box<dyn Hittable>,
@@ -11,17 +23,141 @@ pub struct BVHNode {
     bounding_box: Aabb,
 }
 
+// 进程内唯一的建树方法 需要在加载场景之前设定
+static BUILDER: AtomicU8 = AtomicU8::new(BvhBuilder::Linear as u8);
+
+pub fn set_builder(builder: BvhBuilder) {
+    BUILDER.store(builder as u8, Ordering::Relaxed);
+}
+
+// SAH 代价以求交一个物体为单位
+const TRAVERSAL_COST: f64 = 1.0;
+const BINS: usize = 12;
+// 物体数不超过该值 且不划分的代价更低时成为叶子
+const MAX_LEAF_SIZE: usize = 4;
+// 物体数不少于该值时 划分与两棵子树在渲染用的线程池中并行进行
+const PARALLEL_MIN: usize = 1024;
+// 超过该深度后按质心的中位数对半划分
+// 物体分布极不均匀时 SAH 每次可能只分出一个物体 树的深度会随物体数线性增长
+pub(crate) const MAX_SAH_DEPTH: usize = 48;
+
+// 树的质量 进程中建过的所有树累加
+#[derive(Copy, Clone, Default)]
+pub struct TreeQuality {
+    pub trees: usize,
+    pub nodes: usize,
+    pub leaves: usize,
+    pub primitives: usize,
+    pub max_depth: usize,
+    // 各树的 SAH 代价之和 每棵树按根节点的表面积归一
+    pub sah_cost: f64,
+    // 建树时间之和
+    pub build_time: Duration,
+}
+
+static TREES: AtomicUsize = AtomicUsize::new(0);
+static NODES: AtomicUsize = AtomicUsize::new(0);
+static LEAVES: AtomicUsize = AtomicUsize::new(0);
+static PRIMITIVES: AtomicUsize = AtomicUsize::new(0);
+static MAX_DEPTH: AtomicUsize = AtomicUsize::new(0);
+static SAH_COST: AtomicU64 = AtomicU64::new(0);
+static BUILD_NANOS: AtomicU64 = AtomicU64::new(0);
+
+pub fn quality() -> TreeQuality {
+    TreeQuality {
+        trees: TREES.load(Ordering::Relaxed),
+        nodes: NODES.load(Ordering::Relaxed),
+        leaves: LEAVES.load(Ordering::Relaxed),
+        primitives: PRIMITIVES.load(Ordering::Relaxed),
+        max_depth: MAX_DEPTH.load(Ordering::Relaxed),
+        sah_cost: f64::from_bits(SAH_COST.load(Ordering::Relaxed)),
+        build_time: Duration::from_nanos(BUILD_NANOS.load(Ordering::Relaxed)),
+    }
+}
+
+// 建一棵树时的累计 面积尚未归一
+#[derive(Default)]
+pub(crate) struct Tally {
+    inner_area: f64,
+    // 叶子的面积乘以其中的物体数
+    leaf_area: f64,
+    pub nodes: usize,
+    pub leaves: usize,
+    primitives: usize,
+    pub max_depth: usize,
+}
+
+impl Tally {
+    pub(crate) fn inner(&mut self, bounding_box: &Aabb) {
+        self.inner_area += bounding_box.surface_area();
+        self.nodes += 1;
+    }
+
+    pub(crate) fn leaf(&mut self, bounding_box: &Aabb, count: usize, depth: usize) {
+        self.leaf_area += bounding_box.surface_area() * count as f64;
+        self.leaves += 1;
+        self.primitives += count;
+        self.max_depth = self.max_depth.max(depth);
+    }
+
+    fn merge(&mut self, other: &Tally) {
+        self.inner_area += other.inner_area;
+        self.leaf_area += other.leaf_area;
+        self.nodes += other.nodes;
+        self.leaves += other.leaves;
+        self.primitives += other.primitives;
+        self.max_depth = self.max_depth.max(other.max_depth);
+    }
+
+    pub(crate) fn record(&self, root: &Aabb, build_time: Duration) {
+        let area = root.surface_area();
+        let cost = if area > 0.0 {
+            (TRAVERSAL_COST * self.inner_area + self.leaf_area) / area
+        } else {
+            0.0
+        };
+        TREES.fetch_add(1, Ordering::Relaxed);
+        NODES.fetch_add(self.nodes, Ordering::Relaxed);
+        LEAVES.fetch_add(self.leaves, Ordering::Relaxed);
+        PRIMITIVES.fetch_add(self.primitives, Ordering::Relaxed);
+        MAX_DEPTH.fetch_max(self.max_depth, Ordering::Relaxed);
+        BUILD_NANOS.fetch_add(build_time.as_nanos() as u64, Ordering::Relaxed);
+        SAH_COST
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + cost).to_bits())
+            })
+            .unwrap();
+    }
+}
+
+// 建树时物体的包围盒只算一次
+pub(crate) struct Primitive {
+    pub object: Box<dyn Hittable>,
+    pub bounding_box: Aabb,
+    pub centroid: Vec3,
+}
+
+fn surrounding(boxes: impl Iterator<Item = Aabb>) -> Option<Aabb> {
+    boxes.reduce(|a, b| Aabb::surrounding_box(&a, &b))
+}
+
 impl BVHNode {
     fn create_tree(
         mut objects: Vec<Box<dyn Hittable>>,
         time0: f64,
         time1: f64,
+        rng: &mut StdRng,
+        depth: usize,
+        tally: &mut Tally,
     ) -> Box<dyn Hittable> {
         match objects.len() {
             0 => panic!("[BVH] len mismatch"),
-            1 => objects.remove(0),
+            1 => {
+                let object = objects.remove(0);
+                tally.leaf(&object.bounding_box(time0, time1).unwrap(), 1, depth);
+                object
+            }
             _ => {
-                let mut rng = rand::thread_rng();
                 let axis = rng.gen_range(0..3);
                 objects.sort_by(|a, b| {
                     a.bounding_box(time0, time1).unwrap().min[axis]
@@ -31,12 +167,13 @@ impl BVHNode {
 
                 let mut a = objects;
                 let b = a.split_off(a.len() / 2);
-                let left = Self::create_tree(a, time0, time1);
-                let right = Self::create_tree(b, time0, time1);
+                let left = Self::create_tree(a, time0, time1, rng, depth + 1, tally);
+                let right = Self::create_tree(b, time0, time1, rng, depth + 1, tally);
                 let bounding_box = Aabb::surrounding_box(
                     &left.bounding_box(time0, time1).unwrap(),
                     &right.bounding_box(time0, time1).unwrap(),
                 );
+                tally.inner(&bounding_box);
                 Box::new(Self {
                     left,
                     right,
@@ -46,18 +183,269 @@ impl BVHNode {
         }
     }
 
+    fn from_build(node: BuildNode) -> Box<dyn Hittable> {
+        match node {
+            BuildNode::Leaf(_, mut objects) => {
+                if objects.len() == 1 {
+                    return objects.pop().unwrap();
+                }
+                Box::new(HittableList { objects })
+            }
+            BuildNode::Inner(bounding_box, _, left, right) => Box::new(Self {
+                left: Self::from_build(*left),
+                right: Self::from_build(*right),
+                bounding_box,
+            }),
+        }
+    }
+
     pub fn create(hittable_list: HittableList, time0: f64, time1: f64) -> Box<dyn Hittable> {
-        Self::create_tree(hittable_list.objects, time0, time1)
+        let builder = BUILDER.load(Ordering::Relaxed);
+        if builder == BvhBuilder::Linear as u8 {
+            return Box::new(LinearBVH::new(hittable_list, time0, time1));
+        }
+        let start = Instant::now();
+        let mut tally = Tally::default();
+        let node = if builder == BvhBuilder::Median as u8 {
+            // 划分轴只影响效率 用固定的种子保证每次建出同样的树 因此只能依次建造
+            let mut rng = StdRng::seed_from_u64(0);
+            Self::create_tree(hittable_list.objects, time0, time1, &mut rng, 0, &mut tally)
+        } else {
+            let primitives = primitives(hittable_list, time0, time1);
+            Self::from_build(build(primitives, 0, &mut tally))
+        };
+        tally.record(&node.bounding_box(time0, time1).unwrap(), start.elapsed());
+        node
+    }
+}
+
+pub(crate) fn primitives(hittable_list: HittableList, time0: f64, time1: f64) -> Vec<Primitive> {
+    hittable_list
+        .objects
+        .into_par_iter()
+        .map(|object| {
+            let bounding_box = object.bounding_box(time0, time1).unwrap();
+            Primitive {
+                object,
+                bounding_box,
+                centroid: bounding_box.centroid(),
+            }
+        })
+        .collect()
+}
+
+fn bounds_of<F>(primitives: &[Primitive], f: F) -> Aabb
+where
+    F: Fn(&Primitive) -> Aabb + Sync + Send,
+{
+    if primitives.len() >= PARALLEL_MIN {
+        primitives
+            .par_iter()
+            .map(f)
+            .reduce_with(|a, b| Aabb::surrounding_box(&a, &b))
+            .unwrap()
+    } else {
+        surrounding(primitives.iter().map(f)).unwrap()
+    }
+}
+
+fn bounds(primitives: &[Primitive]) -> Aabb {
+    bounds_of(primitives, |p| p.bounding_box)
+}
+
+// SAH 建出的树 转换成 BVHNode 或展开成数组
+pub(crate) enum BuildNode {
+    Leaf(Aabb, Vec<Box<dyn Hittable>>),
+    // 包围盒 划分轴 两个孩子
+    Inner(Aabb, usize, Box<BuildNode>, Box<BuildNode>),
+}
+
+pub(crate) fn build(primitives: Vec<Primitive>, depth: usize, tally: &mut Tally) -> BuildNode {
+    let bounding_box = bounds(&primitives);
+    match split(primitives, &bounding_box, depth) {
+        Split::Leaf(primitives) => {
+            tally.leaf(&bounding_box, primitives.len(), depth);
+            let objects = primitives.into_iter().map(|p| p.object).collect();
+            BuildNode::Leaf(bounding_box, objects)
+        }
+        Split::Inner(left, right, axis) => {
+            tally.inner(&bounding_box);
+            // 两棵子树各自累计 合并后与依次建造的结果相同
+            let (left, right) = if left.len() + right.len() >= PARALLEL_MIN {
+                let mut right_tally = Tally::default();
+                let (left, right) = rayon::join(
+                    || build(left, depth + 1, tally),
+                    || build(right, depth + 1, &mut right_tally),
+                );
+                tally.merge(&right_tally);
+                (left, right)
+            } else {
+                (
+                    build(left, depth + 1, tally),
+                    build(right, depth + 1, tally),
+                )
+            };
+            BuildNode::Inner(bounding_box, axis, Box::new(left), Box::new(right))
+        }
     }
 }
 
+// SAH 划分的结果 两边的物体与划分轴
+enum Split {
+    Leaf(Vec<Primitive>),
+    Inner(Vec<Primitive>, Vec<Primitive>, usize),
+}
+
+fn split(mut primitives: Vec<Primitive>, bounding_box: &Aabb, depth: usize) -> Split {
+    let n = primitives.len();
+    if n == 1 {
+        return Split::Leaf(primitives);
+    }
+    let centroids = bounds_of(&primitives, |p| Aabb::new(p.centroid, p.centroid));
+    let extent = centroids.max - centroids.min;
+    let axis = if extent.x > extent.y && extent.x > extent.z {
+        0
+    } else if extent.y > extent.z {
+        1
+    } else {
+        2
+    };
+    if depth >= MAX_SAH_DEPTH {
+        if n <= MAX_LEAF_SIZE {
+            return Split::Leaf(primitives);
+        }
+        primitives.select_nth_unstable_by(n / 2, |a, b| {
+            a.centroid[axis]
+                .partial_cmp(&b.centroid[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let right = primitives.split_off(n / 2);
+        return Split::Inner(primitives, right, axis);
+    }
+    let (min, extent) = (centroids.min[axis], extent[axis]);
+    let best = if extent > 0.0 {
+        best_split(&primitives, axis, min, extent)
+    } else {
+        None
+    };
+
+    // 划分的代价 = 遍历一个节点 + 两边物体数按面积加权
+    let leaf = match best {
+        Some((cost, _)) => {
+            n <= MAX_LEAF_SIZE && n as f64 <= TRAVERSAL_COST + cost / bounding_box.surface_area()
+        }
+        None => n <= MAX_LEAF_SIZE,
+    };
+    if leaf {
+        return Split::Leaf(primitives);
+    }
+    match best {
+        Some((_, bins)) => {
+            let left_of = |p: &Primitive| bin(p.centroid[axis], min, extent) < bins;
+            let (left, right) = if n >= PARALLEL_MIN {
+                primitives.into_par_iter().partition(left_of)
+            } else {
+                primitives.into_iter().partition(left_of)
+            };
+            Split::Inner(left, right, axis)
+        }
+        // 质心都重合 物体太多时只能对半分
+        None => {
+            let right = primitives.split_off(n / 2);
+            Split::Inner(primitives, right, axis)
+        }
+    }
+}
+
+// 各桶的物体数与包围盒
+#[derive(Default)]
+struct Bins {
+    counts: [usize; BINS],
+    boxes: [Option<Aabb>; BINS],
+}
+
+impl Bins {
+    fn add(&mut self, b: usize, bounding_box: Aabb) {
+        self.counts[b] += 1;
+        self.boxes[b] = surrounding(self.boxes[b].into_iter().chain(Some(bounding_box)));
+    }
+
+    fn merge(mut self, other: Bins) -> Bins {
+        for b in 0..BINS {
+            self.counts[b] += other.counts[b];
+            self.boxes[b] = surrounding(self.boxes[b].into_iter().chain(other.boxes[b]));
+        }
+        self
+    }
+}
+
+// 沿质心范围最大的轴把物体分到若干桶中 在桶的边界中找代价最低的划分
+// 返回划分的代价与左边的桶数
+fn best_split(
+    primitives: &[Primitive],
+    axis: usize,
+    min: f64,
+    extent: f64,
+) -> Option<(f64, usize)> {
+    let add = |mut bins: Bins, p: &Primitive| {
+        bins.add(bin(p.centroid[axis], min, extent), p.bounding_box);
+        bins
+    };
+    let Bins { counts, boxes } = if primitives.len() >= PARALLEL_MIN {
+        primitives
+            .par_iter()
+            .fold(Bins::default, add)
+            .reduce(Bins::default, Bins::merge)
+    } else {
+        primitives.iter().fold(Bins::default(), add)
+    };
+    // 从右往左累计 right[i] 为第 i 个桶及其右边的面积与物体数
+    let mut right = [(0.0, 0); BINS];
+    let mut acc: Option<Aabb> = None;
+    let mut count = 0;
+    for i in (1..BINS).rev() {
+        acc = surrounding(acc.into_iter().chain(boxes[i]));
+        count += counts[i];
+        right[i] = (acc.map_or(0.0, |b| b.surface_area()), count);
+    }
+    let mut best = None;
+    let mut acc: Option<Aabb> = None;
+    let mut count = 0;
+    for i in 0..BINS - 1 {
+        acc = surrounding(acc.into_iter().chain(boxes[i]));
+        count += counts[i];
+        let (right_area, right_count) = right[i + 1];
+        if count == 0 || right_count == 0 {
+            continue;
+        }
+        let left_area = acc.map_or(0.0, |b| b.surface_area());
+        let cost = left_area * count as f64 + right_area * right_count as f64;
+        if best.map_or(true, |(best_cost, _)| cost < best_cost) {
+            best = Some((cost, i + 1));
+        }
+    }
+    best
+}
+
+// 质心所在的桶
+fn bin(c: f64, min: f64, extent: f64) -> usize {
+    (((c - min) / extent * BINS as f64) as usize).min(BINS - 1)
+}
+
 impl Hittable for BVHNode {
-    fn hit(&self, ray: crate::ray::Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: crate::ray::Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        stats::count(Counter::BvhNodes);
         if !self.bounding_box.hit(ray, t_min, t_max) {
             return None;
         }
-        let hit_left = self.left.hit(ray, t_min, t_max);
-        let hit_right = self.right.hit(ray, t_min, t_max);
+        let hit_left = self.left.hit(ray, t_min, t_max, sampler);
+        let hit_right = self.right.hit(ray, t_min, t_max, sampler);
         match (hit_left, hit_right) {
             (None, None) => None,
             (Some(hit_record), None) => Some(hit_record),
@@ -76,3 +464,122 @@ impl Hittable for BVHNode {
         Some(self.bounding_box)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::SamplerType;
+    use crate::material::Lambertian;
+    use crate::ray::Ray;
+    use crate::sampler;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+
+    fn spheres(centers: &[Vec3], radius: f64) -> HittableList {
+        let material = Lambertian::new(SolidColor::new(Vec3::one()));
+        let mut list = HittableList::new();
+        for &center in centers {
+            list.add(Box::new(Sphere::new(center, radius, material)));
+        }
+        list
+    }
+
+    fn random_centers(n: usize) -> Vec<Vec3> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..n).map(|_| Vec3::random(&mut rng) * 100.0).collect()
+    }
+
+    fn sah(list: HittableList) -> (BuildNode, Tally) {
+        let mut tally = Tally::default();
+        let node = build(primitives(list, 0.0, 1.0), 0, &mut tally);
+        (node, tally)
+    }
+
+    fn leaf_sizes(node: &BuildNode, sizes: &mut Vec<usize>) {
+        match node {
+            BuildNode::Leaf(_, objects) => sizes.push(objects.len()),
+            BuildNode::Inner(_, _, left, right) => {
+                leaf_sizes(left, sizes);
+                leaf_sizes(right, sizes);
+            }
+        }
+    }
+
+    #[test]
+    fn sah_keeps_every_primitive() {
+        let n = 2000;
+        let (node, tally) = sah(spheres(&random_centers(n), 1.0));
+        let mut sizes = vec![];
+        leaf_sizes(&node, &mut sizes);
+        assert_eq!(sizes.iter().sum::<usize>(), n);
+        assert!(sizes
+            .iter()
+            .all(|&size| (1..=MAX_LEAF_SIZE).contains(&size)));
+        assert_eq!(tally.primitives, n);
+        assert_eq!(tally.leaves, sizes.len());
+        assert_eq!(tally.nodes + 1, tally.leaves);
+    }
+
+    #[test]
+    fn sah_splits_between_clusters() {
+        let mut centers = vec![];
+        for k in 0..8 {
+            centers.push(Vec3::new(k as f64, 0.0, 0.0));
+            centers.push(Vec3::new(1000.0 + k as f64, 0.0, 0.0));
+        }
+        let (node, _) = sah(spheres(&centers, 0.4));
+        match node {
+            BuildNode::Inner(_, axis, left, right) => {
+                assert_eq!(axis, 0);
+                for (child, side) in [(left, 0.0), (right, 1000.0)] {
+                    let bounding_box = match *child {
+                        BuildNode::Leaf(bounding_box, _) => bounding_box,
+                        BuildNode::Inner(bounding_box, _, _, _) => bounding_box,
+                    };
+                    assert!(bounding_box.min.x >= side - 1.0);
+                    assert!(bounding_box.max.x <= side + 8.0);
+                }
+            }
+            BuildNode::Leaf(_, _) => panic!("16 spheres should be split"),
+        }
+    }
+
+    #[test]
+    fn sah_halves_coincident_centroids() {
+        let n = 64;
+        let (_, tally) = sah(spheres(&vec![Vec3::zero(); n], 1.0));
+        assert_eq!(tally.primitives, n);
+        assert!(tally.max_depth <= 5);
+    }
+
+    #[test]
+    fn sah_depth_is_bounded() {
+        // 每个物体到原点的距离翻倍 SAH 每次只能分出最远的一个
+        let n = 300;
+        let centers: Vec<Vec3> = (0..n)
+            .map(|k| Vec3::new(2.0_f64.powi(k as i32), 0.0, 0.0))
+            .collect();
+        let (_, tally) = sah(spheres(&centers, 0.1));
+        assert_eq!(tally.primitives, n);
+        assert!(tally.max_depth > 40);
+        assert!(tally.max_depth <= MAX_SAH_DEPTH + 8);
+    }
+
+    #[test]
+    fn sah_tree_finds_the_closest_hit() {
+        let centers = random_centers(500);
+        let (node, _) = sah(spheres(&centers, 2.0));
+        let bvh = BVHNode::from_build(node);
+        let list = spheres(&centers, 2.0);
+        let mut sampler = sampler::create(SamplerType::Independent, 0, 0, 0, 1);
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..1000 {
+            let origin = Vec3::random(&mut rng) * 100.0;
+            let direction = Vec3::random_unit_vector(&mut rng);
+            let ray = Ray::new(origin, direction, 0.0);
+            let expected = list.hit(ray, 0.001, f64::INFINITY, sampler.as_mut());
+            let actual = bvh.hit(ray, 0.001, f64::INFINITY, sampler.as_mut());
+            assert_eq!(expected.map(|h| h.t), actual.map(|h| h.t));
+        }
+    }
+}
@@ -1,6 +1,7 @@
 // 相机类
 
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
 use rand::Rng;
 
@@ -60,14 +61,13 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * Vec3::concentric_disk(sampler.get_2d());
         let offset = self.u * rd.x + self.v * rd.y;
-        let mut rng = rand::thread_rng();
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-            rng.gen_range(self.time0..self.time1),
+            sampler.gen_range(self.time0..self.time1),
         )
     }
 }
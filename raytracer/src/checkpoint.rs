@@ -0,0 +1,340 @@
+// 断点
+// 把累计的采样保存到磁盘 之后可以从断点继续渲染
+// 格式说明见 doc/checkpoint.md 全部数据为小端序
+//
+//   magic    8 字节  "RTCKPT\0\0"
+//   version  u32     3 也能读取没有 buckets 的版本 2
+//   width    u32
+//   height   u32
+//   sampler  u32     0 independent 1 stratified 2 halton 3 sobol
+//   seed     u64
+//   spp      u32     渲染时的采样数上限 分层采样据此划分
+//   filter   u32     0 box 1 gaussian 2 mitchell 3 lanczos 4 blackman-harris
+//   radius   f64     滤波器半径
+//   buckets  u32     采样分组数 1 表示直接取平均
+//   像素     width * height 个 按行存放 第 0 行在图像顶部
+//     mean   3 x f64 工作空间中的均值
+//     m2     f64     亮度与均值之差的平方和
+//     count  u32     采样数
+//     每组一项 共 buckets 项
+//       sum    3 x f64 按滤波器权重累加的颜色
+//       weight f64     权重之和
+
+use crate::cli::{FilterType, SamplerType};
+use crate::render::{Accumulator, PixelStats, Splat};
+use crate::vec3::Color;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"RTCKPT\0\0";
+const VERSION: u32 = 3;
+const HEADER_SIZE: usize = 52;
+const STATS_SIZE: usize = 36;
+const SPLAT_SIZE: usize = 32;
+
+// 断点所属的渲染 继续渲染时需要一致
+#[derive(Copy, Clone)]
+pub struct CheckpointInfo {
+    pub width: u32,
+    pub height: u32,
+    pub sampler: SamplerType,
+    pub seed: u64,
+    pub samples_per_pixel: u32,
+    pub filter: FilterType,
+    pub filter_radius: f64,
+    pub buckets: u32,
+}
+
+impl CheckpointInfo {
+    // 分层采样的分层依赖采样数上限 其余采样器可以继续加采样
+    pub fn resumable_as(&self, other: &CheckpointInfo) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.sampler == other.sampler
+            && self.seed == other.seed
+            && self.filter == other.filter
+            && self.filter_radius == other.filter_radius
+            && self.buckets == other.buckets
+            && (self.sampler != SamplerType::Stratified
+                || self.samples_per_pixel == other.samples_per_pixel)
+    }
+}
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(err) => write!(f, "{}", err),
+            CheckpointError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError {
+    fn from(err: io::Error) -> Self {
+        CheckpointError::Io(err)
+    }
+}
+
+const SAMPLERS: [SamplerType; 4] = [
+    SamplerType::Independent,
+    SamplerType::Stratified,
+    SamplerType::Halton,
+    SamplerType::Sobol,
+];
+
+const FILTERS: [FilterType; 5] = [
+    FilterType::Box,
+    FilterType::Gaussian,
+    FilterType::Mitchell,
+    FilterType::Lanczos,
+    FilterType::BlackmanHarris,
+];
+
+// 先写到临时文件再改名 写到一半时进程退出也不会损坏旧的断点
+pub fn save(
+    path: &Path,
+    info: &CheckpointInfo,
+    accumulator: &Accumulator,
+) -> Result<(), CheckpointError> {
+    let temp = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&temp)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&info.width.to_le_bytes())?;
+    out.write_all(&info.height.to_le_bytes())?;
+    let sampler = SAMPLERS.iter().position(|&s| s == info.sampler).unwrap() as u32;
+    out.write_all(&sampler.to_le_bytes())?;
+    out.write_all(&info.seed.to_le_bytes())?;
+    out.write_all(&info.samples_per_pixel.to_le_bytes())?;
+    let filter = FILTERS.iter().position(|&f| f == info.filter).unwrap() as u32;
+    out.write_all(&filter.to_le_bytes())?;
+    out.write_all(&info.filter_radius.to_le_bytes())?;
+    out.write_all(&info.buckets.to_le_bytes())?;
+    let buckets = accumulator.splats.chunks(info.buckets as usize);
+    for (p, splats) in accumulator.pixels.iter().zip(buckets) {
+        for v in [p.mean.x, p.mean.y, p.mean.z, p.m2] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        out.write_all(&p.count.to_le_bytes())?;
+        for s in splats {
+            for v in [s.sum.x, s.sum.y, s.sum.z, s.weight] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    out.into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<(CheckpointInfo, Accumulator), CheckpointError> {
+    let bytes = fs::read(path)?;
+    let invalid = |message: &str| CheckpointError::Invalid(message.to_string());
+    if bytes.len() < HEADER_SIZE - 4 || &bytes[..8] != MAGIC {
+        return Err(invalid("not a checkpoint file"));
+    }
+    let u32_at =
+        |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    let u64_at = |i: usize| u64::from(u32_at(i)) | u64::from(u32_at(i + 4)) << 32;
+    // 版本 2 没有分组数 文件头短 4 字节
+    let (header_size, buckets) = match u32_at(8) {
+        // 版本 1 没有滤波累加的颜色 无法由它得到图像
+        1 => return Err(invalid("version 1 checkpoints have no filtered samples")),
+        2 => (HEADER_SIZE - 4, 1),
+        VERSION if bytes.len() >= HEADER_SIZE => (HEADER_SIZE, u32_at(48)),
+        VERSION => return Err(invalid("truncated checkpoint file")),
+        _ => return Err(invalid("unsupported checkpoint version")),
+    };
+    if buckets == 0 {
+        return Err(invalid("checkpoint has no sample buckets"));
+    }
+    let info = CheckpointInfo {
+        width: u32_at(12),
+        height: u32_at(16),
+        sampler: *SAMPLERS
+            .get(u32_at(20) as usize)
+            .ok_or_else(|| invalid("unknown sampler"))?,
+        seed: u64_at(24),
+        samples_per_pixel: u32_at(32),
+        filter: *FILTERS
+            .get(u32_at(36) as usize)
+            .ok_or_else(|| invalid("unknown filter"))?,
+        filter_radius: f64::from_bits(u64_at(40)),
+        buckets,
+    };
+    let count = info.width as usize * info.height as usize;
+    let pixel_size = STATS_SIZE + buckets as usize * SPLAT_SIZE;
+    if bytes.len() != header_size + count * pixel_size {
+        return Err(invalid("truncated checkpoint file"));
+    }
+
+    let mut accumulator = Accumulator::new(info.width, info.height, buckets);
+    let splats = accumulator.splats.chunks_mut(buckets as usize);
+    let pixels = accumulator.pixels.iter_mut().zip(splats);
+    for (k, (p, splats)) in pixels.enumerate() {
+        let i = header_size + k * pixel_size;
+        let f64_at = |offset: usize| f64::from_bits(u64_at(i + offset));
+        *p = PixelStats {
+            mean: Color::new(f64_at(0), f64_at(8), f64_at(16)),
+            m2: f64_at(24),
+            count: u32_at(i + 32),
+        };
+        for (b, s) in splats.iter_mut().enumerate() {
+            let j = STATS_SIZE + b * SPLAT_SIZE;
+            *s = Splat {
+                sum: Color::new(f64_at(j), f64_at(j + 8), f64_at(j + 16)),
+                weight: f64_at(j + 24),
+            };
+        }
+    }
+    Ok((info, accumulator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracer-{}-{}", std::process::id(), name))
+    }
+
+    fn info(buckets: u32) -> CheckpointInfo {
+        CheckpointInfo {
+            width: 3,
+            height: 2,
+            sampler: SamplerType::Sobol,
+            seed: 0x0123_4567_89ab_cdef,
+            samples_per_pixel: 64,
+            filter: FilterType::Mitchell,
+            filter_radius: 2.0,
+            buckets,
+        }
+    }
+
+    // 每个数各不相同 读错位置就能发现
+    fn filled(info: &CheckpointInfo) -> Accumulator {
+        let mut accumulator = Accumulator::new(info.width, info.height, info.buckets);
+        for (k, p) in accumulator.pixels.iter_mut().enumerate() {
+            let k = k as f64;
+            *p = PixelStats {
+                mean: Color::new(k, k + 0.25, k + 0.5),
+                m2: -k,
+                count: 10 + k as u32,
+            };
+        }
+        for (k, s) in accumulator.splats.iter_mut().enumerate() {
+            let k = k as f64 * 100.0;
+            *s = Splat {
+                sum: Color::new(k + 1.0, k + 2.0, k + 3.0),
+                weight: k + 4.0,
+            };
+        }
+        accumulator
+    }
+
+    fn assert_same(a: &Accumulator, b: &Accumulator) {
+        assert_eq!(
+            (a.width, a.height, a.buckets),
+            (b.width, b.height, b.buckets)
+        );
+        for (p, q) in a.pixels.iter().zip(&b.pixels) {
+            assert_eq!(
+                (p.mean.x, p.mean.y, p.mean.z, p.m2, p.count),
+                (q.mean.x, q.mean.y, q.mean.z, q.m2, q.count)
+            );
+        }
+        for (s, t) in a.splats.iter().zip(&b.splats) {
+            assert_eq!(
+                (s.sum.x, s.sum.y, s.sum.z, s.weight),
+                (t.sum.x, t.sum.y, t.sum.z, t.weight)
+            );
+        }
+    }
+
+    fn load_bytes(
+        name: &str,
+        bytes: &[u8],
+    ) -> Result<(CheckpointInfo, Accumulator), CheckpointError> {
+        let path = temp_path(name);
+        fs::write(&path, bytes).unwrap();
+        let result = load(&path);
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn round_trip() {
+        for &buckets in &[1, 3] {
+            let info = info(buckets);
+            let accumulator = filled(&info);
+            let path = temp_path(&format!("round_trip_{}.ckpt", buckets));
+            save(&path, &info, &accumulator).unwrap();
+            let (loaded, back) = load(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert!(loaded.resumable_as(&info) && info.resumable_as(&loaded));
+            assert_eq!(loaded.samples_per_pixel, info.samples_per_pixel);
+            assert_same(&back, &accumulator);
+        }
+    }
+
+    // 版本 2 的文件头没有分组数 每个像素只有一组
+    #[test]
+    fn loads_version_2() {
+        let info = info(1);
+        let accumulator = filled(&info);
+        let path = temp_path("version_2.ckpt");
+        save(&path, &info, &accumulator).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        bytes[8..12].copy_from_slice(&2u32.to_le_bytes());
+        bytes.drain(HEADER_SIZE - 4..HEADER_SIZE);
+
+        let (loaded, back) = load_bytes("version_2_edited.ckpt", &bytes).unwrap();
+        assert!(loaded.resumable_as(&info));
+        assert_same(&back, &accumulator);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let info = info(2);
+        let path = temp_path("bad.ckpt");
+        save(&path, &info, &filled(&info)).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut version_1 = bytes.clone();
+        version_1[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let mut future = bytes.clone();
+        future[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let mut no_buckets = bytes.clone();
+        no_buckets[48..52].copy_from_slice(&0u32.to_le_bytes());
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        for (name, bytes) in [
+            ("version_1", version_1),
+            ("future", future),
+            ("no_buckets", no_buckets),
+            ("magic", magic),
+            ("truncated", bytes[..bytes.len() - 1].to_vec()),
+            ("header", bytes[..20].to_vec()),
+        ] {
+            let result = load_bytes(name, &bytes);
+            assert!(
+                matches!(result, Err(CheckpointError::Invalid(_))),
+                "{}",
+                name
+            );
+        }
+    }
+}
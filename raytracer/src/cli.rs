@@ -0,0 +1,342 @@
+// 命令行参数
+
+use crate::denoise;
+use crate::vec3::Vec3;
+use clap::{Parser, ValueEnum};
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[clap(name = "raytracer", about = "Summer ray tracer")]
+pub struct Args {
+    /// Scene to render, see --list-scenes
+    #[clap(short, long, default_value = "the_world")]
+    pub scene: String,
+
+    /// Scene description file (.json or .toml), overrides --scene
+    #[clap(long)]
+    pub scene_file: Option<PathBuf>,
+
+    /// List all available scenes and exit
+    #[clap(long)]
+    pub list_scenes: bool,
+
+    /// Image width in pixels
+    #[clap(short, long, default_value_t = 600)]
+    pub width: u32,
+
+    /// Image height in pixels, defaults to a 3:2 aspect ratio
+    #[clap(long)]
+    pub height: Option<u32>,
+
+    /// Samples per pixel
+    #[clap(long, default_value_t = 180)]
+    pub spp: u32,
+
+    /// Stop sampling a pixel once the standard error of its luminance falls below this fraction of
+    /// the mean, --spp becomes the upper limit
+    #[clap(long)]
+    pub adaptive_threshold: Option<f64>,
+
+    /// Samples every pixel takes before --adaptive-threshold is checked
+    #[clap(long, default_value_t = 16)]
+    pub min_spp: u32,
+
+    /// Also write the number of samples taken per pixel as a heat map
+    #[clap(long)]
+    pub heatmap: Option<PathBuf>,
+
+    /// Preview image path, defaults to the output path with ".preview" before the extension
+    #[clap(long)]
+    pub preview: Option<PathBuf>,
+
+    /// Do not write a preview image
+    #[clap(long)]
+    pub no_preview: bool,
+
+    /// Samples per pixel of the first pass, after which the preview is first written
+    #[clap(long, default_value_t = 1)]
+    pub preview_spp: u32,
+
+    /// Preview is this many times smaller than the output in each direction
+    #[clap(long, default_value_t = 4)]
+    pub preview_scale: u32,
+
+    /// Minimum seconds between two preview refreshes
+    #[clap(long, default_value_t = 30.0)]
+    pub preview_interval: f64,
+
+    /// Samples per pixel added in each progressive pass
+    #[clap(long, default_value_t = 16)]
+    pub pass_spp: u32,
+
+    /// Save the accumulated samples to this file so the render can be resumed
+    #[clap(long)]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Minimum seconds between two checkpoints, one is also written when rendering ends
+    #[clap(long, default_value_t = 300.0)]
+    pub checkpoint_interval: f64,
+
+    /// Continue from a checkpoint and add samples until --spp, keeps checkpointing to the same file
+    /// unless --checkpoint is given
+    #[clap(long)]
+    pub resume: Option<PathBuf>,
+
+    /// Stop after this many minutes and write the image rendered so far
+    #[clap(long)]
+    pub time_limit: Option<f64>,
+
+    /// Maximum ray bounce depth with --termination fixed
+    #[clap(long, default_value_t = 25)]
+    pub max_depth: i32,
+
+    /// How paths are terminated
+    #[clap(long, value_enum, default_value_t = Termination::Roulette)]
+    pub termination: Termination,
+
+    /// Bounces before Russian roulette may end a path
+    #[clap(long, default_value_t = 3)]
+    pub rr_depth: i32,
+
+    /// How diffuse bounces choose their directions
+    #[clap(long, value_enum, default_value_t = Sampling::Mis)]
+    pub sampling: Sampling,
+
+    /// Sample generator for pixel, lens and light/material dimensions
+    #[clap(long, value_enum, default_value_t = SamplerType::Sobol)]
+    pub sampler: SamplerType,
+
+    /// Scale down a sample's direct light, reaching the camera after at most one bounce, whose
+    /// luminance exceeds this limit
+    #[clap(long)]
+    pub clamp_direct: Option<f64>,
+
+    /// Scale down a sample's indirect light whose luminance exceeds this limit
+    #[clap(long)]
+    pub clamp_indirect: Option<f64>,
+
+    /// How the samples of a pixel are combined
+    #[clap(long, value_enum, default_value_t = Estimator::Mean)]
+    pub estimator: Estimator,
+
+    /// Sample buckets for --estimator median-of-means
+    #[clap(long, default_value_t = 8)]
+    pub mom_buckets: u32,
+
+    /// How the bounding volume hierarchy over the scene is built
+    #[clap(long, value_enum, default_value_t = BvhBuilder::Linear)]
+    pub bvh: BvhBuilder,
+
+    /// Pixel reconstruction filter that samples are splatted with
+    #[clap(long, value_enum, default_value_t = FilterType::Box)]
+    pub filter: FilterType,
+
+    /// Filter radius in pixels, defaults to 0.5 for box, 1.5 for gaussian and blackman-harris and 2
+    /// for mitchell and lanczos
+    #[clap(long)]
+    pub filter_radius: Option<f64>,
+
+    /// First-hit buffers to write along with the image, as layers of an .exr output or as separate
+    /// images named after the output
+    #[clap(long, value_enum, use_value_delimiter = true)]
+    pub aov: Vec<Aov>,
+
+    /// Samples per pixel of the first-hit buffers
+    #[clap(long, default_value_t = 16)]
+    pub aov_spp: u32,
+
+    /// Denoise the output guided by the albedo and normal buffers
+    #[clap(long)]
+    pub denoise: bool,
+
+    /// Rounds of the denoising filter, each one reaches twice as far
+    #[clap(long, default_value_t = 5, value_parser = parse_denoise_iterations)]
+    pub denoise_iterations: u32,
+
+    /// How strongly the denoiser smooths across color differences, must be positive
+    #[clap(long, default_value_t = 1.0, value_parser = parse_denoise_strength)]
+    pub denoise_strength: f32,
+
+    /// Also write the render statistics as JSON to this file
+    #[clap(long)]
+    pub stats_json: Option<PathBuf>,
+
+    /// Number of render threads, defaults to the number of available cores
+    #[clap(short = 'j', long)]
+    pub threads: Option<usize>,
+
+    /// Seed for scene generation and sampling, equal seeds give identical images
+    #[clap(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Output image path, .exr, .hdr and .pfm keep linear radiance, other formats are tone mapped
+    #[clap(short, long, default_value = "output/objtest/image4.jpg")]
+    pub output: PathBuf,
+
+    /// Linear color space that textures, materials and lights are converted to for rendering
+    #[clap(long, value_enum, default_value_t = WorkingSpace::Rec709)]
+    pub working_space: WorkingSpace,
+
+    /// Exposure in stops applied before tone mapping
+    #[clap(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    pub exposure: f64,
+
+    /// Color temperature in kelvin that is corrected to neutral white
+    #[clap(long)]
+    pub white_balance: Option<f64>,
+
+    /// Curve that maps linear radiance to the displayable range
+    #[clap(long, value_enum, default_value_t = ToneMapper::Clamp)]
+    pub tonemap: ToneMapper,
+
+    /// Tone map an existing linear .exr or .pfm render to --output instead of rendering
+    #[clap(long)]
+    pub tonemap_from: Option<PathBuf>,
+
+    /// Camera position as x,y,z
+    #[clap(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub lookfrom: Option<Vec3>,
+
+    /// Camera target as x,y,z
+    #[clap(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub lookat: Option<Vec3>,
+
+    /// Vertical field of view in degrees
+    #[clap(long)]
+    pub vfov: Option<f64>,
+
+    /// Lens aperture
+    #[clap(long)]
+    pub aperture: Option<f64>,
+
+    /// Focus distance
+    #[clap(long)]
+    pub focus_dist: Option<f64>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Sampling {
+    /// Sample the material only
+    Bsdf,
+    /// Mix material sampling with sampling toward the scene lights
+    Light,
+    /// Sample both and combine them with the power heuristic
+    Mis,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Termination {
+    /// Cut every path off at --max-depth
+    Fixed,
+    /// Russian roulette after --rr-depth bounces
+    Roulette,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum SamplerType {
+    /// Independent uniform random numbers
+    Independent,
+    /// Jittered strata, shuffled per dimension
+    Stratified,
+    /// Halton sequence with its digits Owen-scrambled per pixel
+    Halton,
+    /// Owen-scrambled Sobol sequence
+    Sobol,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Estimator {
+    /// Weighted mean of all samples
+    Mean,
+    /// Median of the means of sample buckets, rejects rare outliers at the cost of some bias
+    MedianOfMeans,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum BvhBuilder {
+    /// Surface area heuristic tree flattened into one array, traversed near child first
+    Linear,
+    /// Surface area heuristic over binned centroids, leaves hold a few primitives
+    Sah,
+    /// Random axis split at the median of the box minimums, one primitive per leaf
+    Median,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum FilterType {
+    /// Every sample counts fully for the pixel it falls in
+    Box,
+    /// Truncated Gaussian with a standard deviation of 0.5 pixels
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3
+    Mitchell,
+    /// Sinc windowed by a sinc as wide as the radius
+    Lanczos,
+    /// Four-term Blackman-Harris window
+    BlackmanHarris,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Aov {
+    /// Shading normal facing the camera
+    Normal,
+    /// Distance from the camera along the ray
+    Depth,
+    /// World space position
+    Position,
+    /// Reflectance of the material
+    Albedo,
+    /// Texture coordinates
+    Uv,
+    /// Number of the scene object, objects grouped into a BVH are numbered one by one
+    ObjectId,
+    /// Number of the material
+    MaterialId,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum WorkingSpace {
+    /// Linear sRGB primaries
+    Rec709,
+    /// ACES AP1 primaries
+    Acescg,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ToneMapper {
+    /// Clip everything above 1
+    Clamp,
+    /// x / (1 + x)
+    Reinhard,
+    /// Hable's Uncharted 2 filmic curve
+    Hable,
+    /// Fitted ACES reference and sRGB output transforms
+    Aces,
+}
+
+pub fn parse_denoise_iterations(s: &str) -> Result<u32, String> {
+    let iterations = s.parse::<u32>().map_err(|e| e.to_string())?;
+    if iterations > denoise::MAX_ITERATIONS {
+        return Err(format!("at most {} rounds", denoise::MAX_ITERATIONS));
+    }
+    Ok(iterations)
+}
+
+pub fn parse_denoise_strength(s: &str) -> Result<f32, String> {
+    let strength = s.parse::<f32>().map_err(|e| e.to_string())?;
+    if !(strength > 0.0 && strength.is_finite()) {
+        return Err(format!("expected a positive number but got \"{}\"", s));
+    }
+    Ok(strength)
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let v = s
+        .split(',')
+        .map(|x| x.trim().parse::<f64>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match v[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("expected x,y,z but got \"{}\"", s)),
+    }
+}
@@ -0,0 +1,169 @@
+// 颜色空间
+// 线性 Rec.709 与 XYZ 的转换 色温与白平衡 sRGB 传递函数
+// 场景中的颜色都以 Rec.709 给出 加载时转换到工作空间 渲染结果再转换回 Rec.709
+
+use crate::cli::WorkingSpace;
+use crate::vec3::Color;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub type Mat3 = [[f64; 3]; 3];
+
+pub const REC709_TO_XYZ: Mat3 = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175_0],
+    [0.019_333_9, 0.119_192_0, 0.950_304_1],
+];
+
+pub const XYZ_TO_REC709: Mat3 = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266_0, 1.876_010_8, 0.041_556_0],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+
+// Bradford 锥响应矩阵
+const BRADFORD: Mat3 = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+const BRADFORD_INV: Mat3 = [
+    [0.986_992_9, -0.147_054_3, 0.159_962_7],
+    [0.432_305_3, 0.518_360_3, 0.049_291_2],
+    [-0.008_528_5, 0.040_042_8, 0.968_486_7],
+];
+
+const REC709_TO_ACESCG: Mat3 = [
+    [0.613_097_4, 0.339_523_1, 0.047_379_5],
+    [0.070_193_7, 0.916_353_9, 0.013_452_4],
+    [0.020_615_6, 0.109_569_8, 0.869_814_7],
+];
+
+const ACESCG_TO_REC709: Mat3 = [
+    [1.704_858_6, -0.621_716_0, -0.083_142_6],
+    [-0.130_076_8, 1.140_735_7, -0.010_658_9],
+    [-0.023_964_0, -0.128_975_5, 1.152_939_5],
+];
+
+// 纹理数据的编码
+#[derive(Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    // 经过 sRGB 传递函数编码 8 位图片通常如此
+    Srgb,
+    // 线性 Rec.709
+    Linear,
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        ColorSpace::Srgb
+    }
+}
+
+// 进程内唯一的工作空间 需要在加载场景之前设定
+static ACESCG: AtomicBool = AtomicBool::new(false);
+
+pub fn set_working_space(space: WorkingSpace) {
+    ACESCG.store(space == WorkingSpace::Acescg, Ordering::Relaxed);
+}
+
+// Rec.709 转到工作空间
+pub fn to_working(c: Color) -> Color {
+    if ACESCG.load(Ordering::Relaxed) {
+        mul(&REC709_TO_ACESCG, c)
+    } else {
+        c
+    }
+}
+
+// 工作空间转回 Rec.709
+pub fn from_working(c: Color) -> Color {
+    if ACESCG.load(Ordering::Relaxed) {
+        mul(&ACESCG_TO_REC709, c)
+    } else {
+        c
+    }
+}
+
+// 工作空间中的亮度
+pub fn luminance(c: Color) -> f64 {
+    if ACESCG.load(Ordering::Relaxed) {
+        0.272_228_7 * c.x + 0.674_081_8 * c.y + 0.053_689_5 * c.z
+    } else {
+        0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+    }
+}
+
+pub fn mul(m: &Mat3, c: Color) -> Color {
+    Color::new(
+        m[0][0] * c.x + m[0][1] * c.y + m[0][2] * c.z,
+        m[1][0] * c.x + m[1][1] * c.y + m[1][2] * c.z,
+        m[2][0] * c.x + m[2][1] * c.y + m[2][2] * c.z,
+    )
+}
+
+pub fn mul_mat(a: &Mat3, b: &Mat3) -> Mat3 {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+// 黑体辐射在某一色温下的色度坐标 xy
+// 见 Kim et al., Design of Advanced Color Temperature Control System for HDTV Applications, 2002
+fn planckian_xy(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+    let x = if t <= 4000.0 {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_037_9e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x3 - 5.873_386_70 * x2 + 3.751_129_97 * x - 0.370_014_83
+    };
+    (x, y)
+}
+
+// 把色温为 kelvin 的白色校正为中性的 Rec.709 线性变换
+// 以同一曲线上 6504K 的点为参照 因此 6504K 不改变图像
+pub fn white_balance(kelvin: f64) -> Mat3 {
+    let to_lms = |(x, y): (f64, f64)| mul(&BRADFORD, Color::new(x / y, 1.0, (1.0 - x - y) / y));
+    let src = to_lms(planckian_xy(kelvin));
+    let dst = to_lms(planckian_xy(6504.0));
+    let scale = [
+        [dst.x / src.x, 0.0, 0.0],
+        [0.0, dst.y / src.y, 0.0],
+        [0.0, 0.0, dst.z / src.z],
+    ];
+    let adapt = mul_mat(&BRADFORD_INV, &mul_mat(&scale, &BRADFORD));
+    mul_mat(&XYZ_TO_REC709, &mul_mat(&adapt, &REC709_TO_XYZ))
+}
+
+// sRGB 的电光转换函数 即 srgb_oetf 的逆
+pub fn srgb_eotf(x: f64) -> f64 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// sRGB 的光电转换函数 输入输出都在 [0, 1] 内
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
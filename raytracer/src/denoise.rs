@@ -0,0 +1,147 @@
+// 降噪
+// 以反照率与法线为引导的边缘保持 à-trous 小波滤波
+// 见 Dammertz et al., Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination Filtering, 2010
+// 先除去反照率 只对光照滤波 纹理的细节不会被抹平
+
+use crate::film::Film;
+use rayon::prelude::*;
+
+// B3 样条 每轮的采样间隔加倍
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// 边缘阈值 颜色的阈值每轮减半
+const SIGMA_COLOR: f32 = 0.3;
+const SIGMA_NORMAL: f32 = 0.3;
+const SIGMA_ALBEDO: f32 = 0.1;
+
+// 轮数的上限 此时采样间隔已达 2^15 像素 再多也不会更平滑
+pub const MAX_ITERATIONS: u32 = 16;
+
+// 反照率低于该值的通道不除 如背景与光源
+const MIN_ALBEDO: f32 = 0.01;
+
+fn distance2(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+// 压缩高亮 亮斑不会主导颜色的差异
+fn compress(c: [f32; 3]) -> [f32; 3] {
+    [
+        c[0] / (1.0 + c[0]),
+        c[1] / (1.0 + c[1]),
+        c[2] / (1.0 + c[2]),
+    ]
+}
+
+// 三个缓冲的尺寸需要相同 strength 越大越平滑
+// 轮数超过 MAX_ITERATIONS 时按上限计 strength 不是正数时不滤波
+pub fn denoise(
+    beauty: &Film,
+    albedo: &Film,
+    normal: &Film,
+    iterations: u32,
+    strength: f32,
+) -> Film {
+    let (width, height) = (beauty.width as usize, beauty.height as usize);
+    let factor: Vec<[f32; 3]> = albedo
+        .pixels
+        .iter()
+        .map(|a| a.map(|v| if v > MIN_ALBEDO { v } else { 1.0 }))
+        .collect();
+    let mut light: Vec<[f32; 3]> = beauty
+        .pixels
+        .iter()
+        .zip(&factor)
+        .map(|(c, a)| [c[0] / a[0], c[1] / a[1], c[2] / a[2]])
+        .collect();
+
+    let iterations = if strength > 0.0 {
+        iterations.min(MAX_ITERATIONS)
+    } else {
+        0
+    };
+    for i in 0..iterations {
+        let step = 1 << i;
+        let sigma_color = SIGMA_COLOR * strength / (1 << i) as f32;
+        let compressed: Vec<[f32; 3]> = light.iter().map(|&c| compress(c)).collect();
+        let mut next = vec![[0.0; 3]; width * height];
+        next.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            for (x, out) in row.iter_mut().enumerate() {
+                let p = y * width + x;
+                let mut sum = [0.0; 3];
+                let mut total = 0.0;
+                for (ky, wy) in KERNEL.iter().enumerate() {
+                    let qy = y as isize + (ky as isize - 2) * step;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (kx, wx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (kx as isize - 2) * step;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+                        let w = wx
+                            * wy
+                            * (-distance2(compressed[p], compressed[q])
+                                / (sigma_color * sigma_color))
+                                .exp()
+                            * (-distance2(normal.pixels[p], normal.pixels[q])
+                                / (SIGMA_NORMAL * SIGMA_NORMAL))
+                                .exp()
+                            * (-distance2(albedo.pixels[p], albedo.pixels[q])
+                                / (SIGMA_ALBEDO * SIGMA_ALBEDO))
+                                .exp();
+                        for (s, v) in sum.iter_mut().zip(light[q]) {
+                            *s += w * v;
+                        }
+                        total += w;
+                    }
+                }
+                // 中心像素的权重总大于 0
+                *out = sum.map(|s| s / total);
+            }
+        });
+        light = next;
+    }
+
+    let mut result = Film::new(beauty.width, beauty.height);
+    result.samples = beauty.samples.clone();
+    result.metadata = beauty.metadata.clone();
+    for ((out, c), a) in result.pixels.iter_mut().zip(&light).zip(&factor) {
+        *out = [c[0] * a[0], c[1] * a[1], c[2] * a[2]];
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noisy(width: u32, height: u32) -> Film {
+        let mut film = Film::new(width, height);
+        for (k, pixel) in film.pixels.iter_mut().enumerate() {
+            let v = if k % 3 == 0 { 1.0 } else { 0.2 };
+            *pixel = [v, v, v];
+        }
+        film
+    }
+
+    #[test]
+    fn zero_strength_keeps_the_image() {
+        let beauty = noisy(8, 8);
+        let albedo = Film::new(8, 8);
+        let normal = Film::new(8, 8);
+        let result = denoise(&beauty, &albedo, &normal, 5, 0.0);
+        assert_eq!(result.pixels, beauty.pixels);
+    }
+
+    #[test]
+    fn many_iterations_stay_finite() {
+        let beauty = noisy(8, 8);
+        let albedo = Film::new(8, 8);
+        let normal = Film::new(8, 8);
+        let result = denoise(&beauty, &albedo, &normal, 40, 1.0);
+        assert!(result.pixels.iter().flatten().all(|v| v.is_finite()));
+    }
+}
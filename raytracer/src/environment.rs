@@ -0,0 +1,172 @@
+// 环境光
+// 背景纹理按方向取值 并按亮度进行重要性采样
+
+use crate::color::luminance;
+use crate::pdf::Pdf;
+use crate::sampler::Sampler;
+use crate::texture::Texture;
+use crate::vec3::{Color, Vec3};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+// 构造分布时对背景纹理取样的分辨率
+const ENV_WIDTH: usize = 256;
+const ENV_HEIGHT: usize = 128;
+
+// 分段常数的一维分布
+struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    sum: f64,
+}
+
+impl Distribution1D {
+    fn new(func: Vec<f64>) -> Self {
+        let n = func.len() as f64;
+        let mut cdf = vec![0.0; func.len() + 1];
+        for i in 0..func.len() {
+            cdf[i + 1] = cdf[i] + func[i] / n;
+        }
+        let sum = cdf[func.len()];
+        if sum > 0.0 {
+            for c in cdf.iter_mut() {
+                *c /= sum;
+            }
+        }
+        Self { func, cdf, sum }
+    }
+
+    // 返回 [0, 1) 上的采样值 以及所在的段
+    fn sample(&self, r: f64) -> (f64, usize) {
+        let i = match self.cdf.binary_search_by(|c| c.partial_cmp(&r).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }
+        .min(self.func.len() - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (r - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        ((i as f64 + offset) / self.func.len() as f64, i)
+    }
+
+    // 第 i 段上的概率密度
+    fn pdf(&self, i: usize) -> f64 {
+        self.func[i] / self.sum
+    }
+}
+
+pub struct Environment {
+    pub texture: Arc<dyn Texture>,
+    // 行的边缘分布 以及每一行内的条件分布
+    marginal: Option<Distribution1D>,
+    conditional: Vec<Distribution1D>,
+}
+
+impl Environment {
+    pub fn new(texture: Arc<dyn Texture>) -> Self {
+        let conditional: Vec<_> = (0..ENV_HEIGHT)
+            .map(|j| {
+                let v = (j as f64 + 0.5) / ENV_HEIGHT as f64;
+                let sin_theta = (v * PI).sin();
+                Distribution1D::new(
+                    (0..ENV_WIDTH)
+                        .map(|i| {
+                            let u = (i as f64 + 0.5) / ENV_WIDTH as f64;
+                            luminance(texture.value(u, v, Vec3::zero())) * sin_theta
+                        })
+                        .collect(),
+                )
+            })
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|row| row.sum).collect());
+        // 全黑的背景不参与采样
+        let marginal = if marginal.sum > 0.0 {
+            Some(marginal)
+        } else {
+            None
+        };
+        Self {
+            texture,
+            marginal,
+            conditional,
+        }
+    }
+
+    pub fn can_sample(&self) -> bool {
+        self.marginal.is_some()
+    }
+
+    // 方向与纹理坐标的对应关系
+    fn direction_to_uv(direction: Vec3) -> (f64, f64) {
+        let unit_dir = Vec3::unit_vector(direction);
+        let theta = unit_dir.y.clamp(-1.0, 1.0).acos();
+        let phi = (-unit_dir.z).atan2(unit_dir.x) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(u: f64, v: f64) -> Vec3 {
+        let theta = v * PI;
+        let phi = u * 2.0 * PI - PI;
+        Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            -theta.sin() * phi.sin(),
+        )
+    }
+
+    pub fn value(&self, direction: Vec3, origin: Vec3) -> Color {
+        let (u, v) = Self::direction_to_uv(direction);
+        self.texture.value(u, v, origin)
+    }
+
+    // 立体角上的概率密度
+    pub fn pdf_value(&self, direction: Vec3) -> f64 {
+        let marginal = match &self.marginal {
+            Some(marginal) => marginal,
+            None => return 0.0,
+        };
+        let (u, v) = Self::direction_to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let i = ((u * ENV_WIDTH as f64) as usize).min(ENV_WIDTH - 1);
+        let j = ((v * ENV_HEIGHT as f64) as usize).min(ENV_HEIGHT - 1);
+        marginal.pdf(j) * self.conditional[j].pdf(i) / (2.0 * PI * PI * sin_theta)
+    }
+
+    pub fn random(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let marginal = match &self.marginal {
+            Some(marginal) => marginal,
+            None => return Vec3::sphere_direction(sampler.get_2d()),
+        };
+        let (r1, r2) = sampler.get_2d();
+        let (v, j) = marginal.sample(r1);
+        let (u, _) = self.conditional[j].sample(r2);
+        Self::uv_to_direction(u, v)
+    }
+}
+
+// 按环境光分布采样方向
+pub struct EnvironmentPdf<'a> {
+    environment: &'a Environment,
+}
+
+impl<'a> EnvironmentPdf<'a> {
+    pub fn new(environment: &'a Environment) -> Self {
+        Self { environment }
+    }
+}
+
+impl<'a> Pdf for EnvironmentPdf<'a> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.environment.pdf_value(direction)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.environment.random(sampler)
+    }
+}
@@ -0,0 +1,125 @@
+// 胶片
+// 线性的 f32 RGB 缓冲 保留超过 1.0 的辐射亮度 输出时再决定如何映射
+
+use crate::color;
+use crate::tonemap::ToneMapping;
+use crate::vec3::Color;
+use image::{ImageBuffer, RgbImage};
+
+// 像素按行存放 第 0 行在图像顶部
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+    // 每个像素的采样数
+    pub samples: Vec<u32>,
+    // 渲染设置 写入支持的文件格式的文件头
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        let pixels = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            pixels: vec![[0.0; 3]; pixels],
+            samples: vec![0; pixels],
+            metadata: vec![],
+        }
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Color) {
+        self.pixels[(y * self.width + x) as usize] =
+            [color.x as f32, color.y as f32, color.z as f32];
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        let [r, g, b] = self.pixels[(y * self.width + x) as usize];
+        Color::new(r as f64, g as f64, b as f64)
+    }
+
+    // 某个通道的全部数据
+    pub fn channel(&self, c: usize) -> Vec<f32> {
+        self.pixels.iter().map(|p| p[c]).collect()
+    }
+
+    // 采样数的热度图 从黑到黄对应 0 到 max_samples
+    pub fn heatmap(&self, max_samples: u32) -> Film {
+        const STOPS: [[f64; 3]; 5] = [
+            [0.0, 0.0, 0.02],
+            [0.34, 0.06, 0.43],
+            [0.73, 0.21, 0.33],
+            [0.98, 0.55, 0.04],
+            [0.99, 1.0, 0.64],
+        ];
+        let mut film = Film::new(self.width, self.height);
+        for (p, &count) in film.pixels.iter_mut().zip(&self.samples) {
+            let t = (count as f64 / max_samples.max(1) as f64).clamp(0.0, 1.0) * 4.0;
+            let k = (t as usize).min(3);
+            let f = t - k as f64;
+            for (c, v) in p.iter_mut().enumerate() {
+                // 色标按显示值给出 存为线性值
+                *v = color::srgb_eotf(STOPS[k][c] * (1.0 - f) + STOPS[k + 1][c] * f) as f32;
+            }
+        }
+        film
+    }
+
+    // 按 factor x factor 的方块求平均缩小 采样数相加
+    pub fn downsample(&self, factor: u32) -> Film {
+        let mut film = Film::new((self.width / factor).max(1), (self.height / factor).max(1));
+        let mut weights = vec![0u32; film.pixels.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (i, j) = (
+                    (x / factor).min(film.width - 1),
+                    (y / factor).min(film.height - 1),
+                );
+                let k = (j * film.width + i) as usize;
+                let src = (y * self.width + x) as usize;
+                for c in 0..3 {
+                    film.pixels[k][c] += self.pixels[src][c];
+                }
+                film.samples[k] += self.samples[src];
+                weights[k] += 1;
+            }
+        }
+        for (p, w) in film.pixels.iter_mut().zip(weights) {
+            for v in p.iter_mut() {
+                *v /= w as f32;
+            }
+        }
+        film
+    }
+
+    pub fn average_samples(&self) -> f64 {
+        self.samples.iter().map(|&s| s as f64).sum::<f64>() / self.samples.len().max(1) as f64
+    }
+
+    // 写入文件头的采样数 中断 超时或自适应采样时实际完成的采样少于请求的数目
+    // stopped 为渲染提前停下的原因
+    pub fn sample_metadata(&self, requested: u32, stopped: Option<&str>) -> Vec<(String, String)> {
+        let min = self.samples.iter().copied().min().unwrap_or(0);
+        vec![
+            ("samplesPerPixel", min.to_string()),
+            (
+                "meanSamplesPerPixel",
+                format!("{:.2}", self.average_samples()),
+            ),
+            ("requestedSamplesPerPixel", requested.to_string()),
+            ("stopped", stopped.unwrap_or("no").to_string()),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+
+    // 映射到 8 位图像 用于 PNG 与 JPEG
+    pub fn to_rgb8(&self, tone: &ToneMapping) -> RgbImage {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let (r, g, b) = tone.to_u8(self.get(x, y));
+            image::Rgb([r, g, b])
+        })
+    }
+}
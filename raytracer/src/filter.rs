@@ -0,0 +1,200 @@
+// 像素重建滤波器
+// 每个采样按滤波器的权重累加到半径内的所有像素 滤波器都是可分离的 f(x, y) = f(x) f(y)
+
+use crate::cli::FilterType;
+use std::f64::consts::PI;
+
+pub trait Filter: Send + Sync {
+    // 超出半径的权重为 0 单位为像素
+    fn radius(&self) -> f64;
+
+    // 一维权重 x 为到像素中心的距离
+    fn evaluate(&self, x: f64) -> f64;
+}
+
+// 未给出半径时使用各滤波器常用的半径
+pub fn create(filter_type: FilterType, radius: Option<f64>) -> Box<dyn Filter> {
+    match filter_type {
+        FilterType::Box => Box::new(BoxFilter {
+            radius: radius.unwrap_or(0.5),
+        }),
+        FilterType::Gaussian => Box::new(GaussianFilter::new(radius.unwrap_or(1.5), 0.5)),
+        FilterType::Mitchell => Box::new(MitchellFilter {
+            radius: radius.unwrap_or(2.0),
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        }),
+        FilterType::Lanczos => Box::new(LanczosFilter {
+            radius: radius.unwrap_or(2.0),
+        }),
+        FilterType::BlackmanHarris => Box::new(BlackmanHarrisFilter {
+            radius: radius.unwrap_or(1.5),
+        }),
+    }
+}
+
+// 盒式滤波 半径 0.5 时每个采样只属于所在的像素
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    // 半开区间 恰好落在像素边界上的采样只计入一侧的像素
+    fn evaluate(&self, x: f64) -> f64 {
+        if -self.radius <= x && x < self.radius {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+// 高斯滤波 减去半径处的值使权重在边界处降到 0
+pub struct GaussianFilter {
+    radius: f64,
+    sigma: f64,
+    edge: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, sigma: f64) -> Self {
+        Self {
+            radius,
+            sigma,
+            edge: gaussian(radius, sigma),
+        }
+    }
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64 {
+    (-x * x / (2.0 * sigma * sigma)).exp()
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        (gaussian(x, self.sigma) - self.edge).max(0.0)
+    }
+}
+
+// Mitchell-Netravali 三次滤波 B = C = 1/3
+// 见 Mitchell and Netravali, Reconstruction Filters in Computer Graphics, 1988
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        // 把半径缩放到 [0, 2]
+        let x = (2.0 * x / self.radius).abs();
+        let (b, c) = (self.b, self.c);
+        let v = if x < 1.0 {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b)
+        } else if x < 2.0 {
+            (-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c)
+        } else {
+            0.0
+        };
+        v / 6.0
+    }
+}
+
+// 以 sinc 为窗口的 sinc 滤波 窗口宽度等于半径
+pub struct LanczosFilter {
+    radius: f64,
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        if x.abs() >= self.radius {
+            return 0.0;
+        }
+        sinc(x) * sinc(x / self.radius)
+    }
+}
+
+// 四项 Blackman-Harris 窗口
+pub struct BlackmanHarrisFilter {
+    radius: f64,
+}
+
+impl Filter for BlackmanHarrisFilter {
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    fn evaluate(&self, x: f64) -> f64 {
+        if x.abs() >= self.radius {
+            return 0.0;
+        }
+        // 窗口的中心对准像素中心
+        let t = 2.0 * PI * (x / (2.0 * self.radius) + 0.5);
+        0.35875 - 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() - 0.01168 * (3.0 * t).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_filter_is_half_open() {
+        let filter = BoxFilter { radius: 0.5 };
+        assert_eq!(filter.evaluate(-0.5), 1.0);
+        assert_eq!(filter.evaluate(0.5), 0.0);
+        // 落在两个像素交界处的采样只计入一个像素
+        let x = 1.0;
+        let weights: f64 = (0..3).map(|i| filter.evaluate(i as f64 + 0.5 - x)).sum();
+        assert_eq!(weights, 1.0);
+    }
+
+    #[test]
+    fn filters_vanish_at_radius() {
+        let filters: Vec<Box<dyn Filter>> = vec![
+            Box::new(GaussianFilter::new(1.5, 0.5)),
+            Box::new(MitchellFilter {
+                radius: 2.0,
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            }),
+            Box::new(LanczosFilter { radius: 3.0 }),
+            Box::new(BlackmanHarrisFilter { radius: 1.5 }),
+        ];
+        for filter in filters {
+            let r = filter.radius();
+            assert!(filter.evaluate(0.0) > 0.0);
+            assert!(filter.evaluate(r).abs() < 1e-12);
+            assert!(filter.evaluate(-r).abs() < 1e-12);
+        }
+    }
+}
@@ -4,8 +4,27 @@ use crate::aabb::Aabb;
 use crate::hittable::*;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::stats::{self, Counter};
 use crate::vec3::Vec3;
 
+// 光源采样时 射中面积为 area 的矩形的概率密度
+fn rect_pdf_value(
+    hit_record: Option<HitRecord>,
+    direction: Vec3,
+    direction_along_normal: f64,
+    area: f64,
+) -> f64 {
+    match hit_record {
+        Some(hit_record) => {
+            let distance_squared = hit_record.t * hit_record.t * direction.length_squared();
+            let cosine = (direction_along_normal / direction.length()).abs();
+            distance_squared / (cosine * area)
+        }
+        None => 0.0,
+    }
+}
+
 // 轴对齐的x-y平面
 #[derive(Debug, Copy, Clone)]
 pub struct XYRect<M: Material> {
@@ -28,10 +47,9 @@ impl<M: Material> XYRect<M> {
             material,
         }
     }
-}
 
-impl<M: Material> Hittable for XYRect<M> {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    // 三种矩形的求交都不用随机数 pdf_value 直接调用 不计入统计
+    fn intersect(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let t = (self.k - ray.origin.z) / ray.direction.z;
         if t < t_min || t > t_max {
             return None;
@@ -51,6 +69,13 @@ impl<M: Material> Hittable for XYRect<M> {
             ray,
         ))
     }
+}
+
+impl<M: Material> Hittable for XYRect<M> {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64, _: &mut dyn Sampler) -> Option<HitRecord> {
+        stats::count(Counter::PrimitiveHits);
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
         Some(Aabb::new(
@@ -58,6 +83,24 @@ impl<M: Material> Hittable for XYRect<M> {
             Vec3::new(self.x1, self.y1, self.k + 0.0001),
         ))
     }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        rect_pdf_value(
+            self.intersect(Ray::new(origin, direction, 0.0), 0.0001, f64::INFINITY),
+            direction,
+            direction.z,
+            (self.x1 - self.x0) * (self.y1 - self.y0),
+        )
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.get_2d();
+        Vec3::new(
+            self.x0 + (self.x1 - self.x0) * r1,
+            self.y0 + (self.y1 - self.y0) * r2,
+            self.k,
+        ) - origin
+    }
 }
 
 // 轴对齐的x-z平面
@@ -82,10 +125,8 @@ impl<M: Material> XZRect<M> {
             material,
         }
     }
-}
 
-impl<M: Material> Hittable for XZRect<M> {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn intersect(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let t = (self.k - ray.origin.y) / ray.direction.y;
         if t < t_min || t > t_max {
             return None;
@@ -105,6 +146,13 @@ impl<M: Material> Hittable for XZRect<M> {
             ray,
         ))
     }
+}
+
+impl<M: Material> Hittable for XZRect<M> {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64, _: &mut dyn Sampler) -> Option<HitRecord> {
+        stats::count(Counter::PrimitiveHits);
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
         Some(Aabb::new(
@@ -112,6 +160,24 @@ impl<M: Material> Hittable for XZRect<M> {
             Vec3::new(self.x1, self.k + 0.0001, self.z1),
         ))
     }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        rect_pdf_value(
+            self.intersect(Ray::new(origin, direction, 0.0), 0.0001, f64::INFINITY),
+            direction,
+            direction.y,
+            (self.x1 - self.x0) * (self.z1 - self.z0),
+        )
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.get_2d();
+        Vec3::new(
+            self.x0 + (self.x1 - self.x0) * r1,
+            self.k,
+            self.z0 + (self.z1 - self.z0) * r2,
+        ) - origin
+    }
 }
 
 // 轴对齐的y-z平面
@@ -136,10 +202,8 @@ impl<M: Material> YZRect<M> {
             material,
         }
     }
-}
 
-impl<M: Material> Hittable for YZRect<M> {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn intersect(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let t = (self.k - ray.origin.x) / ray.direction.x;
         if t < t_min || t > t_max {
             return None;
@@ -159,6 +223,13 @@ impl<M: Material> Hittable for YZRect<M> {
             ray,
         ))
     }
+}
+
+impl<M: Material> Hittable for YZRect<M> {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64, _: &mut dyn Sampler) -> Option<HitRecord> {
+        stats::count(Counter::PrimitiveHits);
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
         Some(Aabb::new(
@@ -166,4 +237,22 @@ impl<M: Material> Hittable for YZRect<M> {
             Vec3::new(self.k + 0.0001, self.y1, self.z1),
         ))
     }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        rect_pdf_value(
+            self.intersect(Ray::new(origin, direction, 0.0), 0.0001, f64::INFINITY),
+            direction,
+            direction.x,
+            (self.y1 - self.y0) * (self.z1 - self.z0),
+        )
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.get_2d();
+        Vec3::new(
+            self.k,
+            self.y0 + (self.y1 - self.y0) * r1,
+            self.z0 + (self.z1 - self.z0) * r2,
+        ) - origin
+    }
 }
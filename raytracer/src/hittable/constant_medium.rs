@@ -2,6 +2,8 @@
 
 use crate::hittable::*;
 use crate::material::Material;
+use crate::sampler::Sampler;
+use crate::stats::{self, Counter};
 use rand::Rng;
 
 pub struct ConstantMedium<M: Material> {
@@ -21,9 +23,22 @@ impl<M: Material> ConstantMedium<M> {
 }
 
 impl<M: Material> Hittable for ConstantMedium<M> {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        match self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY) {
-            Some(mut rec1) => match self.boundary.hit(ray, rec1.t + 0.0001, f64::INFINITY) {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        stats::count(Counter::PrimitiveHits);
+        match self
+            .boundary
+            .hit(ray, f64::NEG_INFINITY, f64::INFINITY, sampler)
+        {
+            Some(mut rec1) => match self
+                .boundary
+                .hit(ray, rec1.t + 0.0001, f64::INFINITY, sampler)
+            {
                 Some(mut rec2) => {
                     rec1.t = rec1.t.max(t_min);
                     rec2.t = rec2.t.min(t_max);
@@ -31,8 +46,7 @@ impl<M: Material> Hittable for ConstantMedium<M> {
                         rec1.t = rec1.t.max(0.0);
                         let ray_length = ray.direction.length();
                         let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-                        let hit_distance =
-                            self.neg_inv_density * rand::thread_rng().gen::<f64>().ln();
+                        let hit_distance = self.neg_inv_density * sampler.gen::<f64>().ln();
                         if hit_distance < distance_inside_boundary {
                             let t = rec1.t + hit_distance / ray_length;
                             Some(HitRecord::new(
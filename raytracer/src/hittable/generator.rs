@@ -9,15 +9,17 @@ use crate::hittable::moving_sphere::MovingSphere;
 use crate::hittable::rectbox::RectBox;
 use crate::hittable::sphere::Sphere;
 use crate::hittable::triangle::Triangle;
+use crate::hittable::Hittable;
 use crate::material::*;
+use crate::matrix::Matrix4;
 use crate::obj_file;
 use crate::texture::*;
 use crate::vec3::{Color, Vec3};
+use rand::rngs::StdRng;
 use rand::Rng;
 
 // 生成随机场景
-pub fn random_scene() -> HittableList {
-    let mut rng = rand::thread_rng();
+pub fn random_scene(rng: &mut StdRng) -> HittableList {
     let mut world = HittableList::new();
 
     let ground_material = Lambertian::new(CheckerTexture::new(
@@ -43,7 +45,7 @@ pub fn random_scene() -> HittableList {
             if (center - Vec3::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 if choose_mat < 0.6 {
                     // diffus
-                    let albedo = SolidColor::new(Color::random() * Color::random());
+                    let albedo = SolidColor::new(Color::random(rng) * Color::random(rng));
                     // 添加了运动的球体
                     let center1 = center + Vec3::new(0.0, rng.gen_range(0.0..0.5), 0.0);
                     world.add(Box::new(MovingSphere::new(
@@ -57,7 +59,7 @@ pub fn random_scene() -> HittableList {
                     // world.add(Box::new(Sphere::new(center, 0.2, Lambertian::new(albedo))));
                 } else if choose_mat < 0.9 {
                     // metal
-                    let albedo = 0.5 * Color::one() + 0.5 * Color::random();
+                    let albedo = 0.5 * Color::one() + 0.5 * Color::random(rng);
                     let fuzz = rng.gen_range(0.0..0.5);
                     world.add(Box::new(Sphere::new(center, 0.2, Metal::new(albedo, fuzz))));
                 } else {
@@ -115,17 +117,17 @@ pub fn two_spheres() -> HittableList {
 }
 
 // 柏林噪声
-pub fn two_perlin_spheres() -> HittableList {
+pub fn two_perlin_spheres(rng: &mut StdRng) -> HittableList {
     let mut objects = HittableList::new();
     objects.add(Box::new(Sphere::new(
         Vec3::new(0.0, -1000.0, 0.0),
         1000.0,
-        Lambertian::new(NoiseTexture::new(4.0)),
+        Lambertian::new(NoiseTexture::new(4.0, rng)),
     )));
     objects.add(Box::new(Sphere::new(
         Vec3::new(0.0, 2.0, 0.0),
         2.0,
-        Lambertian::new(NoiseTexture::new(4.0)),
+        Lambertian::new(NoiseTexture::new(4.0, rng)),
     )));
     objects
 }
@@ -145,31 +147,33 @@ pub fn earth() -> HittableList {
 }
 
 // 发光材质
-pub fn simple_light() -> HittableList {
+pub fn simple_light(lights: &mut HittableList, rng: &mut StdRng) -> HittableList {
     let mut objects = HittableList::new();
     objects.add(Box::new(Sphere::new(
         Vec3::new(0.0, -1000.0, 0.0),
         1000.0,
-        Lambertian::new(NoiseTexture::new(4.0)),
+        Lambertian::new(NoiseTexture::new(4.0, rng)),
     )));
     objects.add(Box::new(Sphere::new(
         Vec3::new(0.0, 2.0, 0.0),
         2.0,
-        Lambertian::new(NoiseTexture::new(4.0)),
+        Lambertian::new(NoiseTexture::new(4.0, rng)),
     )));
-    objects.add(Box::new(XYRect::new(
+    let light = XYRect::new(
         3.0,
         5.0,
         1.0,
         3.0,
         -2.0,
         DiffuseLight::new(SolidColor::new(Color::new(4.0, 4.0, 4.0))),
-    )));
+    );
+    objects.add(Box::new(light));
+    lights.add(Box::new(light));
     objects
 }
 
 // 康奈尔盒子
-pub fn cornell_box() -> HittableList {
+pub fn cornell_box(lights: &mut HittableList) -> HittableList {
     let mut objects = HittableList::new();
 
     let red = Lambertian::new(SolidColor::new(Color::new(0.65, 0.05, 0.05)));
@@ -179,9 +183,9 @@ pub fn cornell_box() -> HittableList {
 
     objects.add(Box::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
     objects.add(Box::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.add(Box::new(XZRect::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    )));
+    let lamp = XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light);
+    objects.add(Box::new(lamp));
+    lights.add(Box::new(lamp));
     objects.add(Box::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white)));
     objects.add(Box::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white)));
     objects.add(Box::new(XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white)));
@@ -197,20 +201,28 @@ pub fn cornell_box() -> HittableList {
         white,
     );
 
-    objects.add(Box::new(Translate::new(
-        Box::new(RotateY::new(Box::new(box1), 15.0)),
+    objects.add(Box::new(rotated_y(
+        Box::new(box1),
+        15.0,
         Vec3::new(265.0, 0.0, 295.0),
     )));
-    objects.add(Box::new(Translate::new(
-        Box::new(RotateY::new(Box::new(box2), -18.0)),
+    objects.add(Box::new(rotated_y(
+        Box::new(box2),
+        -18.0,
         Vec3::new(130.0, 0.0, 65.0),
     )));
 
     objects
 }
 
+// 先绕 y 轴旋转再平移 合成一个变换
+fn rotated_y(object: Box<dyn Hittable>, theta: f64, offset: Vec3) -> Transform {
+    let matrix = Matrix4::rotation(Vec3::new(0.0, 1.0, 0.0), theta).translate(offset);
+    Transform::new(object, matrix).expect("rigid motion is invertible")
+}
+
 // 带有烟块的康奈尔盒子
-pub fn cornell_smoke() -> HittableList {
+pub fn cornell_smoke(lights: &mut HittableList) -> HittableList {
     let mut objects = HittableList::new();
 
     let red = Lambertian::new(SolidColor::new(Color::new(0.65, 0.05, 0.05)));
@@ -220,9 +232,9 @@ pub fn cornell_smoke() -> HittableList {
 
     objects.add(Box::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
     objects.add(Box::new(YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    objects.add(Box::new(XZRect::new(
-        113.0, 443.0, 127.0, 432.0, 554.0, light,
-    )));
+    let lamp = XZRect::new(113.0, 443.0, 127.0, 432.0, 554.0, light);
+    objects.add(Box::new(lamp));
+    lights.add(Box::new(lamp));
     objects.add(Box::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white)));
     objects.add(Box::new(XZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white)));
     objects.add(Box::new(XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white)));
@@ -238,38 +250,33 @@ pub fn cornell_smoke() -> HittableList {
         white,
     );
 
-    objects.add(Box::new(Translate::new(
-        Box::new(RotateY::new(
-            Box::new(ConstantMedium::new(
-                Box::new(box1),
-                0.01,
-                Isotropic::new(SolidColor::new(Color::zero())),
-            )),
-            15.0,
+    objects.add(Box::new(rotated_y(
+        Box::new(ConstantMedium::new(
+            Box::new(box1),
+            0.01,
+            Isotropic::new(SolidColor::new(Color::zero())),
         )),
+        15.0,
         Vec3::new(265.0, 0.0, 295.0),
     )));
-    objects.add(Box::new(Translate::new(
-        Box::new(RotateY::new(
-            Box::new(ConstantMedium::new(
-                Box::new(box2),
-                0.01,
-                Isotropic::new(SolidColor::new(Color::one())),
-            )),
-            -18.0,
+    objects.add(Box::new(rotated_y(
+        Box::new(ConstantMedium::new(
+            Box::new(box2),
+            0.01,
+            Isotropic::new(SolidColor::new(Color::one())),
         )),
+        -18.0,
         Vec3::new(130.0, 0.0, 65.0),
     )));
 
     objects
 }
 
-// 包含所有特性的复杂场景
-pub fn final_scene() -> HittableList {
+// 包含所有特性的复杂场景 返回的物体都已编号
+pub fn final_scene(lights: &mut HittableList, rng: &mut StdRng) -> HittableList {
     let mut boxes1 = HittableList::new();
     let ground = Lambertian::new(SolidColor::new(Color::new(0.48, 0.83, 0.53)));
     let boxes_per_side = 20;
-    let mut rng = rand::thread_rng();
     for i in 0..boxes_per_side {
         for j in 0..boxes_per_side {
             let w = 100.0;
@@ -287,14 +294,17 @@ pub fn final_scene() -> HittableList {
             )))
         }
     }
+    // 地面的每个盒子各自编号 其他物体接着往后编
+    let boxes1 = boxes1.tagged();
+    let mut next_id = boxes1.objects.len() as u32 + 1;
     let mut objects = HittableList {
         objects: vec![BVHNode::create(boxes1, 0.0, 1.0)],
     };
 
     let light = DiffuseLight::new(SolidColor::new(Color::new(7.0, 7.0, 7.0)));
-    objects.add(Box::new(XZRect::new(
-        123.0, 423.0, 147.0, 412.0, 554.0, light,
-    )));
+    let lamp = XZRect::new(123.0, 423.0, 147.0, 412.0, 554.0, light);
+    objects.add(Box::new(lamp));
+    lights.add(Box::new(lamp));
 
     let center0 = Vec3::new(400.0, 400.0, 200.0);
     let center1 = center0 + Vec3::new(30.0, 0.0, 0.0);
@@ -346,22 +356,29 @@ pub fn final_scene() -> HittableList {
     objects.add(Box::new(Sphere::new(
         Vec3::new(220.0, 280.0, 300.0),
         80.0,
-        Lambertian::new(NoiseTexture::new(0.1)),
+        Lambertian::new(NoiseTexture::new(0.1, rng)),
     )));
 
     let mut boxes2 = HittableList::new();
     let white = Lambertian::new(SolidColor::new(Color::new(0.73, 0.73, 0.73)));
     let ns = 1000;
     for _ in 0..ns {
-        boxes2.add(Box::new(Sphere::new(Vec3::random() * 165.0, 10.0, white)))
+        boxes2.add(Box::new(Sphere::new(
+            Vec3::random(rng) * 165.0,
+            10.0,
+            white,
+        )))
     }
 
-    objects.add(Box::new(Translate::new(
-        Box::new(RotateY::new(BVHNode::create(boxes2, 0.0, 1.0), 10.0)),
+    let boxes2 = boxes2.tagged_from(next_id);
+    next_id += boxes2.objects.len() as u32;
+    objects.add(Box::new(rotated_y(
+        BVHNode::create(boxes2, 0.0, 1.0),
+        10.0,
         Vec3::new(-100.0, 270.0, 395.0),
     )));
 
-    objects
+    objects.tagged_from(next_id)
 }
 
 // 三角形 以及测试贴图
@@ -2,8 +2,12 @@
 // 物体需要支持 Hittable 的 trait
 
 use crate::aabb::Aabb;
+use crate::hittable::instance::Tagged;
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+use rand::Rng;
 use std::vec::Vec;
 
 pub struct HittableList {
@@ -21,17 +25,59 @@ impl HittableList {
         self.objects.push(object);
     }
 
+    // 依次给每个物体编号 从 1 开始
+    pub fn tagged(self) -> Self {
+        self.tagged_from(1)
+    }
+
+    // 依次给每个物体编号 从 first 开始
+    // 先给 BVH 里的物体编号时 顶层物体接着往后编 编号不会重复
+    pub fn tagged_from(self, first: u32) -> Self {
+        Self {
+            objects: self
+                .objects
+                .into_iter()
+                .enumerate()
+                .map(|(k, object)| {
+                    Box::new(Tagged::new(object, first + k as u32)) as Box<dyn Hittable>
+                })
+                .collect(),
+        }
+    }
+
+    // 给下标 from 之后加入的物体标上同一个编号 组成同一个物体的几个面共用编号
+    pub fn tag_since(&mut self, from: usize, id: u32) {
+        let objects = self.objects.split_off(from);
+        self.objects.extend(
+            objects
+                .into_iter()
+                .map(|object| Box::new(Tagged::new(object, id)) as Box<dyn Hittable>),
+        );
+    }
+
     // pub fn clear(&mut self) {
     //     self.objects.clear();
     // }
 }
 
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Hittable for HittableList {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
         let mut closet_so_far = t_max;
         let mut hit_result: Option<HitRecord> = None;
         for object in self.objects.iter() {
-            if let Some(hit_rec) = object.hit(ray, t_min, closet_so_far) {
+            if let Some(hit_rec) = object.hit(ray, t_min, closet_so_far, sampler) {
                 closet_so_far = hit_rec.t;
                 hit_result = Some(hit_rec);
             }
@@ -54,4 +100,18 @@ impl Hittable for HittableList {
             )
         }
     }
+
+    // 各物体被选中的概率相同
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(origin, direction))
+            .sum()
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let index = sampler.gen_range(0..self.objects.len());
+        self.objects[index].random(origin, sampler)
+    }
 }
@@ -1,130 +1,426 @@
-// 旋转
+// 仿射变换与实例
 
 use crate::aabb::Aabb;
+use crate::bvh_node::BVHNode;
+use crate::hittable::hittable_list::HittableList;
 use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::matrix::Matrix4;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
+use std::sync::Arc;
 
-pub struct Translate {
-    pub object: Box<dyn Hittable>,
-    pub offset: Vec3,
+// 物体坐标到世界坐标的变换 以及求交时用到的逆矩阵与法线矩阵
+#[derive(Copy, Clone)]
+struct Affine {
+    matrix: Matrix4,
+    inverse: Matrix4,
+    normal: Matrix4,
+    // 线性部分的行列式 换算光源采样的概率密度
+    determinant: f64,
 }
 
-impl Translate {
-    pub fn new(object: Box<dyn Hittable>, offset: Vec3) -> Self {
-        Self { object, offset }
-    }
-}
-
-impl Hittable for Translate {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let moved_r = Ray::new(ray.origin - self.offset, ray.direction, ray.time);
-        self.object.hit(moved_r, t_min, t_max).map(|hit_record| {
-            HitRecord::new(
-                hit_record.point + self.offset,
-                hit_record.t,
-                hit_record.u,
-                hit_record.v,
-                hit_record.normal,
-                hit_record.material,
-                moved_r,
-            )
+impl Affine {
+    // 矩阵不可逆时返回 None 法线矩阵为逆矩阵的转置
+    fn new(matrix: Matrix4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        Some(Self {
+            matrix,
+            inverse,
+            normal: inverse.transpose(),
+            determinant: matrix.determinant3().abs(),
         })
     }
 
-    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
-        self.object.bounding_box(time0, time1).map(|bounding_box| {
-            Aabb::new(
-                bounding_box.min + self.offset,
-                bounding_box.max + self.offset,
-            )
-        })
+    // 方向不归一化 t 在两个坐标系中相同
+    fn ray_to_object(&self, ray: Ray) -> Ray {
+        Ray::new(
+            self.inverse.transform_point(ray.origin),
+            self.inverse.transform_vector(ray.direction),
+            ray.time,
+        )
+    }
+
+    // 法线已按物体坐标中的光线翻转过 变换后朝向不变 因此保留 front_face
+    fn hit_to_world<'a>(&self, hit_record: HitRecord<'a>) -> HitRecord<'a> {
+        HitRecord {
+            point: self.matrix.transform_point(hit_record.point),
+            normal: Vec3::unit_vector(self.normal.transform_vector(hit_record.normal)),
+            ..hit_record
+        }
+    }
+
+    // 变换包围盒的 8 个顶点 仿射变换下得到的盒子包住整个物体
+    fn bounding_box(&self, bbox: Aabb) -> Aabb {
+        let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { bbox.min.x } else { bbox.max.x },
+                if i & 2 == 0 { bbox.min.y } else { bbox.max.y },
+                if i & 4 == 0 { bbox.min.z } else { bbox.max.z },
+            );
+            let p = self.matrix.transform_point(corner);
+            for c in 0..3 {
+                min[c] = min[c].min(p[c]);
+                max[c] = max[c].max(p[c]);
+            }
+        }
+        Aabb::new(min, max)
+    }
+
+    // 物体在自身坐标中按立体角采样 方向经线性变换 L 后的概率密度为
+    // p(w) = p(w_o) |L w_o|^3 / |det L|  其中 w_o 为对应的单位方向
+    fn pdf_value(&self, object: &dyn Hittable, origin: Vec3, direction: Vec3) -> f64 {
+        let local = Vec3::unit_vector(self.inverse.transform_vector(direction));
+        let stretch = self.matrix.transform_vector(local).length();
+        object.pdf_value(self.inverse.transform_point(origin), local) * stretch.powi(3)
+            / self.determinant
+    }
+
+    fn random(&self, object: &dyn Hittable, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let local = object.random(self.inverse.transform_point(origin), sampler);
+        self.matrix.transform_vector(local)
     }
 }
 
-pub struct RotateY {
+// 以 4x4 矩阵变换物体 可以平移 旋转 缩放与错切
+// 多个变换先乘成一个矩阵 求交时只多一次虚函数调用
+// 矩阵不可逆时 (如缩放为零) 构造返回 None
+pub struct Transform {
     pub object: Box<dyn Hittable>,
-    pub sin_theta: f64,
-    pub cos_theta: f64,
-    pub bbox: Option<Aabb>,
+    affine: Affine,
+    bbox: Option<Aabb>,
 }
 
-impl RotateY {
-    pub fn new(object: Box<dyn Hittable>, theta: f64) -> Self {
-        let radians = theta.to_radians();
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        let bbox = object.bounding_box(0.0, 1.0).map(|bbox| {
-            let mut min = Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
-            let mut max = Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
-
-            for i in 0..2 {
-                for j in 0..2 {
-                    for k in 0..2 {
-                        let x = i as f64 * bbox.max.x + (1 - i) as f64 * bbox.min.x;
-                        let y = j as f64 * bbox.max.y + (1 - j) as f64 * bbox.min.y;
-                        let z = k as f64 * bbox.max.z + (1 - k) as f64 * bbox.min.z;
-
-                        let newx = cos_theta * x + sin_theta * z;
-                        let newz = -sin_theta * x + cos_theta * z;
-                        let tester = Vec3::new(newx, y, newz);
-
-                        for c in 0..3 {
-                            min[c] = min[c].min(tester[c]);
-                            max[c] = max[c].max(tester[c]);
-                        }
-                    }
-                }
-            }
-            Aabb::new(min, max)
-        });
-        Self {
+impl Transform {
+    pub fn new(object: Box<dyn Hittable>, matrix: Matrix4) -> Option<Self> {
+        let affine = Affine::new(matrix)?;
+        let bbox = object
+            .bounding_box(0.0, 1.0)
+            .map(|bbox| affine.bounding_box(bbox));
+        Some(Self {
             object,
-            sin_theta,
-            cos_theta,
+            affine,
             bbox,
-        }
+        })
+    }
+
+    pub fn matrix(&self) -> Matrix4 {
+        self.affine.matrix
+    }
+
+    // 在已有的变换之后再施加 matrix
+    pub fn then(self, matrix: Matrix4) -> Option<Self> {
+        let combined = self.affine.matrix.then(matrix);
+        Self::new(self.object, combined)
+    }
+
+    pub fn translate(self, offset: Vec3) -> Option<Self> {
+        self.then(Matrix4::translation(offset))
+    }
+
+    pub fn rotate(self, axis: Vec3, degrees: f64) -> Option<Self> {
+        self.then(Matrix4::rotation(axis, degrees))
+    }
+
+    pub fn scale(self, scale: f64) -> Option<Self> {
+        self.then(Matrix4::scaling(Vec3::new(scale, scale, scale)))
+    }
+
+    pub fn scale_xyz(self, scale: Vec3) -> Option<Self> {
+        self.then(Matrix4::scaling(scale))
+    }
+
+    pub fn look_at(self, from: Vec3, to: Vec3, up: Vec3) -> Option<Self> {
+        self.then(Matrix4::looking_at(from, to, up))
     }
 }
 
-impl Hittable for RotateY {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut origin = ray.origin;
-        let mut direction = ray.direction;
+impl Hittable for Transform {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.object
+            .hit(self.affine.ray_to_object(ray), t_min, t_max, sampler)
+            .map(|hit_record| self.affine.hit_to_world(hit_record))
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.affine
+            .pdf_value(self.object.as_ref(), origin, direction)
+    }
 
-        origin[0] = self.cos_theta * ray.origin[0] - self.sin_theta * ray.origin[2];
-        origin[2] = self.sin_theta * ray.origin[0] + self.cos_theta * ray.origin[2];
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.affine.random(self.object.as_ref(), origin, sampler)
+    }
+}
 
-        direction[0] = self.cos_theta * ray.direction[0] - self.sin_theta * ray.direction[2];
-        direction[2] = self.sin_theta * ray.direction[0] + self.cos_theta * ray.direction[2];
+// 平移与绕 y 轴旋转 只是特定矩阵的 Transform
+pub struct Translate(Transform);
 
-        let rotated_r = Ray::new(origin, direction, ray.time);
+impl Translate {
+    pub fn new(object: Box<dyn Hittable>, offset: Vec3) -> Option<Self> {
+        Transform::new(object, Matrix4::translation(offset)).map(Self)
+    }
+}
 
-        self.object.hit(rotated_r, t_min, t_max).map(|hit_record| {
-            let mut p = hit_record.point;
-            let mut normal = hit_record.normal;
+pub struct RotateY(Transform);
 
-            p[0] = self.cos_theta * hit_record.point[0] + self.sin_theta * hit_record.point[2];
-            p[2] = -self.sin_theta * hit_record.point[0] + self.cos_theta * hit_record.point[2];
+impl RotateY {
+    pub fn new(object: Box<dyn Hittable>, theta: f64) -> Option<Self> {
+        Transform::new(object, Matrix4::rotation(Vec3::new(0.0, 1.0, 0.0), theta)).map(Self)
+    }
+}
 
-            normal[0] =
-                self.cos_theta * hit_record.normal[0] + self.sin_theta * hit_record.normal[2];
-            normal[2] =
-                -self.sin_theta * hit_record.normal[0] + self.cos_theta * hit_record.normal[2];
+macro_rules! forward_hittable {
+    ($wrapper:ty) => {
+        impl Hittable for $wrapper {
+            fn hit(
+                &self,
+                ray: Ray,
+                t_min: f64,
+                t_max: f64,
+                sampler: &mut dyn Sampler,
+            ) -> Option<HitRecord> {
+                self.0.hit(ray, t_min, t_max, sampler)
+            }
+
+            fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+                self.0.bounding_box(time0, time1)
+            }
 
-            HitRecord::new(
-                p,
-                hit_record.t,
-                hit_record.u,
-                hit_record.v,
-                normal,
-                hit_record.material,
-                rotated_r,
-            )
+            fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+                self.0.pdf_value(origin, direction)
+            }
+
+            fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+                self.0.random(origin, sampler)
+            }
+        }
+    };
+}
+
+forward_hittable!(Translate);
+forward_hittable!(RotateY);
+
+// 给物体编号 写入 AOV 的物体编号
+// 嵌套时最内层的编号生效
+pub struct Tagged {
+    pub object: Box<dyn Hittable>,
+    pub id: u32,
+}
+
+impl Tagged {
+    pub fn new(object: Box<dyn Hittable>, id: u32) -> Self {
+        Self { object, id }
+    }
+}
+
+impl Hittable for Tagged {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.object
+            .hit(ray, t_min, t_max, sampler)
+            .map(|mut hit_record| {
+                if hit_record.object_id == 0 {
+                    hit_record.object_id = self.id;
+                }
+                hit_record
+            })
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.object.bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.object.random(origin, sampler)
+    }
+}
+
+// 底层加速结构 (BLAS) 一组物体只建一次BVH 由许多实例共享
+// 实例放进场景的BVH中 就是以实例包围盒建造的顶层加速结构 (TLAS)
+pub fn blas(list: HittableList) -> Arc<dyn Hittable> {
+    if list.objects.is_empty() {
+        return Arc::new(list);
+    }
+    Arc::from(BVHNode::create(list, 0.0, 1.0))
+}
+
+// 共享的几何体摆放在场景中的一份 可以替换全部材质
+// 与 Transform 一样 矩阵不可逆时返回 None
+pub struct Instance {
+    pub blas: Arc<dyn Hittable>,
+    pub material: Option<Arc<dyn Material>>,
+    affine: Affine,
+    bbox: Option<Aabb>,
+}
+
+impl Instance {
+    pub fn new(
+        blas: Arc<dyn Hittable>,
+        matrix: Matrix4,
+        material: Option<Arc<dyn Material>>,
+    ) -> Option<Self> {
+        let affine = Affine::new(matrix)?;
+        let bbox = blas
+            .bounding_box(0.0, 1.0)
+            .map(|bbox| affine.bounding_box(bbox));
+        Some(Self {
+            blas,
+            material,
+            affine,
+            bbox,
         })
     }
+}
+
+impl Hittable for Instance {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.blas
+            .hit(self.affine.ray_to_object(ray), t_min, t_max, sampler)
+            .map(|hit_record| HitRecord {
+                material: match &self.material {
+                    Some(material) => material,
+                    None => hit_record.material,
+                },
+                ..self.affine.hit_to_world(hit_record)
+            })
+    }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
         self.bbox
     }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.affine.pdf_value(self.blas.as_ref(), origin, direction)
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.affine.random(self.blas.as_ref(), origin, sampler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::SamplerType;
+    use crate::hittable::rectbox::RectBox;
+    use crate::material::Lambertian;
+    use crate::sampler;
+    use crate::texture::SolidColor;
+
+    fn unit_box() -> Box<dyn Hittable> {
+        let material = Lambertian::new(SolidColor::new(Vec3::one()));
+        Box::new(RectBox::new(
+            Vec3::zero(),
+            Vec3::new(2.0, 1.0, 1.0),
+            material,
+        ))
+    }
+
+    fn assert_box(bbox: Aabb, min: Vec3, max: Vec3) {
+        assert!(
+            (bbox.min - min).length() < 1e-9,
+            "{:?} != {:?}",
+            bbox.min,
+            min
+        );
+        assert!(
+            (bbox.max - max).length() < 1e-9,
+            "{:?} != {:?}",
+            bbox.max,
+            max
+        );
+    }
+
+    #[test]
+    fn bounding_box_follows_the_transform() {
+        let rotated = Transform::new(
+            unit_box(),
+            Matrix4::rotation(Vec3::new(0.0, 0.0, 1.0), 90.0),
+        )
+        .unwrap();
+        assert_box(
+            rotated.bounding_box(0.0, 1.0).unwrap(),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 1.0),
+        );
+
+        let moved = Transform::new(
+            unit_box(),
+            Matrix4::identity()
+                .scale_xyz(Vec3::new(1.0, 3.0, 1.0))
+                .translate(Vec3::new(5.0, 0.0, -1.0)),
+        )
+        .unwrap();
+        assert_box(
+            moved.bounding_box(0.0, 1.0).unwrap(),
+            Vec3::new(5.0, 0.0, -1.0),
+            Vec3::new(7.0, 3.0, 0.0),
+        );
+
+        // 绕 y 轴转 45 度 盒子包住旋转后的全部顶点
+        let turned = Transform::new(
+            unit_box(),
+            Matrix4::rotation(Vec3::new(0.0, 1.0, 0.0), 45.0),
+        )
+        .unwrap();
+        let h = 0.5_f64.sqrt();
+        assert_box(
+            turned.bounding_box(0.0, 1.0).unwrap(),
+            Vec3::new(0.0, 0.0, -2.0 * h),
+            Vec3::new(3.0 * h, 1.0, h),
+        );
+    }
+
+    #[test]
+    fn transformed_hit_is_in_world_space() {
+        let moved = Translate::new(unit_box(), Vec3::new(0.0, 0.0, 10.0)).unwrap();
+        let mut sampler = sampler::create(SamplerType::Independent, 0, 0, 0, 1);
+        let ray = Ray::new(Vec3::new(1.0, 0.5, 0.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let hit = moved
+            .hit(ray, 0.001, f64::INFINITY, sampler.as_mut())
+            .unwrap();
+        assert!((hit.t - 10.0).abs() < 1e-9);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn singular_transforms_are_rejected() {
+        let flat = Transform::new(unit_box(), Matrix4::identity()).and_then(|t| t.scale(0.0));
+        assert!(flat.is_none());
+        let degenerate = Transform::new(unit_box(), Matrix4::identity())
+            .and_then(|t| t.look_at(Vec3::zero(), Vec3::zero(), Vec3::new(0.0, 1.0, 0.0)));
+        assert!(degenerate.is_none());
+        let tree = blas(HittableList {
+            objects: vec![unit_box()],
+        });
+        assert!(Instance::new(tree, Matrix4::scaling(Vec3::zero()), None).is_none());
+    }
 }
@@ -14,7 +14,9 @@ pub mod triangle;
 use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
+use std::sync::Arc;
 
 #[derive(Copy, Clone)]
 pub struct HitRecord<'a> {
@@ -25,6 +27,8 @@ pub struct HitRecord<'a> {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    // 场景顶层物体的编号 0 表示没有编号
+    pub object_id: u32,
 }
 
 impl<'a> HitRecord<'a> {
@@ -46,6 +50,7 @@ impl<'a> HitRecord<'a> {
             u,
             v,
             front_face,
+            object_id: 0,
         }
     }
 
@@ -62,6 +67,43 @@ impl<'a> HitRecord<'a> {
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    // 介质用采样器决定光线在何处散射
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64, sampler: &mut dyn Sampler)
+        -> Option<HitRecord>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    // 从 origin 沿 direction 射中该物体的概率密度 用于光源采样
+    fn pdf_value(&self, _: Vec3, _: Vec3) -> f64 {
+        0.0
+    }
+
+    // 从 origin 随机指向该物体的方向
+    fn random(&self, _: Vec3, _: &mut dyn Sampler) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+}
+
+// 共享的物体 如同时放在场景与光源列表中
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        (**self).hit(ray, t_min, t_max, sampler)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        (**self).bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        (**self).pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        (**self).random(origin, sampler)
+    }
 }
@@ -4,6 +4,7 @@ use crate::aabb::Aabb;
 use crate::hittable::*;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::stats::{self, Counter};
 use crate::vec3::Vec3;
 use std::f64::consts::PI;
 
@@ -42,7 +43,8 @@ impl<M: Material> MovingSphere<M> {
 }
 
 impl<M: Material> Hittable for MovingSphere<M> {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64, _: &mut dyn Sampler) -> Option<HitRecord> {
+        stats::count(Counter::PrimitiveHits);
         let oc = ray.origin - self.center(ray.time);
         let a = ray.direction.length_squared();
         let half_b = Vec3::dot(oc, ray.direction);
@@ -6,6 +6,7 @@ use crate::hittable::hittable_list::HittableList;
 use crate::hittable::*;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
 
 pub struct RectBox {
@@ -69,11 +70,25 @@ impl RectBox {
 }
 
 impl Hittable for RectBox {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.sides.hit(ray, t_min, t_max)
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        self.sides.hit(ray, t_min, t_max, sampler)
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
         Some(Aabb::new(self.min, self.max))
     }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        self.sides.random(origin, sampler)
+    }
 }
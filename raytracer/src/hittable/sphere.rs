@@ -3,7 +3,10 @@
 use crate::aabb::Aabb;
 use crate::hittable::*;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::stats::{self, Counter};
 use crate::vec3::Vec3;
 use std::f64::consts::PI;
 
@@ -22,10 +25,9 @@ impl<M: Material> Sphere<M> {
             material,
         }
     }
-}
 
-impl<M: Material> Hittable for Sphere<M> {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    // 与光线求交 光源采样计算概率密度时也用它 只有 hit 计入统计
+    fn intersect(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let oc = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = Vec3::dot(oc, ray.direction);
@@ -67,6 +69,13 @@ impl<M: Material> Hittable for Sphere<M> {
             ))
         }
     }
+}
+
+impl<M: Material> Hittable for Sphere<M> {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64, _: &mut dyn Sampler) -> Option<HitRecord> {
+        stats::count(Counter::PrimitiveHits);
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<crate::aabb::Aabb> {
         Some(Aabb::new(
@@ -74,4 +83,35 @@ impl<M: Material> Hittable for Sphere<M> {
             self.center + Vec3::new(self.radius, self.radius, self.radius),
         ))
     }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        if self
+            .intersect(Ray::new(origin, direction, 0.0), 0.0001, f64::INFINITY)
+            .is_none()
+        {
+            return 0.0;
+        }
+        let distance_squared = (self.center - origin).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            // 在球体内部 各方向均匀
+            return 1.0 / (4.0 * PI);
+        }
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return Vec3::sphere_direction(sampler.get_2d());
+        }
+        let uvw = Onb::build_from_w(direction);
+        uvw.local(Vec3::to_sphere_direction(
+            self.radius,
+            distance_squared,
+            sampler.get_2d(),
+        ))
+    }
 }
@@ -4,11 +4,16 @@ use crate::aabb::Aabb;
 use crate::hittable::*;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::stats::{self, Counter};
 use crate::vec3::Vec3;
 
 #[derive(Copy, Clone)]
 pub struct Triangle<M: Material> {
     pub a: Vec3,
+    pub e1: Vec3,
+    pub e2: Vec3,
+    pub area: f64,
     pub n: Vec3,
     pub pc: Vec3,
     pub pb: Vec3,
@@ -33,6 +38,9 @@ impl<M: Material> Triangle<M> {
 
         Self {
             a,
+            e1,
+            e2,
+            area: nel / 2.0,
             n,
             pc: Vec3::cross(e2, n) / nel,
             pb: Vec3::cross(n, e1) / nel,
@@ -40,10 +48,9 @@ impl<M: Material> Triangle<M> {
             material,
         }
     }
-}
 
-impl<M: Material> Hittable for Triangle<M> {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    // pdf_value 也要求交 但不需要采样器 也不计入统计
+    fn intersect(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let oa = ray.origin - self.a;
         let t = -Vec3::dot(oa, self.n) / Vec3::dot(ray.direction, self.n);
         if t < t_min || t > t_max {
@@ -70,8 +77,33 @@ impl<M: Material> Hittable for Triangle<M> {
             }
         }
     }
+}
+
+impl<M: Material> Hittable for Triangle<M> {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64, _: &mut dyn Sampler) -> Option<HitRecord> {
+        stats::count(Counter::PrimitiveHits);
+        self.intersect(ray, t_min, t_max)
+    }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
         Some(self.bbox)
     }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        match self.intersect(Ray::new(origin, direction, 0.0), 0.0001, f64::INFINITY) {
+            Some(hit_record) => {
+                let distance_squared = hit_record.t * hit_record.t * direction.length_squared();
+                let cosine = (Vec3::dot(direction, self.n) / direction.length()).abs();
+                distance_squared / (cosine * self.area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        // 三角形上均匀取点
+        let (r1, r2) = sampler.get_2d();
+        let r1 = r1.sqrt();
+        self.a + self.e1 * (r1 * (1.0 - r2)) + self.e2 * (r1 * r2) - origin
+    }
 }
@@ -0,0 +1,314 @@
+// 积分器
+// 沿光线计算颜色 按 Sampling 选择方向采样的策略
+
+use crate::cli::{Sampling, Termination};
+use crate::color;
+use crate::environment::{Environment, EnvironmentPdf};
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::material::ScatterRecord;
+use crate::pdf::*;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::stats::{self, Counter};
+use crate::vec3::{Color, Vec3};
+use rand::Rng;
+
+pub struct Integrator {
+    pub world: HittableList,
+    // 需要显式采样的光源
+    pub lights: HittableList,
+    pub environment: Environment,
+    pub sampling: Sampling,
+    pub termination: Termination,
+    // 从第几次反射开始进行俄罗斯轮盘赌
+    pub rr_depth: i32,
+    // 每个采样的直接光与间接光的亮度上限 None 表示不限制
+    pub clamp_direct: Option<f64>,
+    pub clamp_indirect: Option<f64>,
+}
+
+// 一条路径带回的光 按经过的反射次数分开
+// 直接光最多经过一次反射 包括相机直接看到的光源
+struct Radiance {
+    direct: Color,
+    indirect: Color,
+}
+
+impl Radiance {
+    fn add(&mut self, bounces: i32, c: Color) {
+        if bounces <= 1 {
+            self.direct += c;
+        } else {
+            self.indirect += c;
+        }
+    }
+}
+
+// 超过上限时按比例缩小 保持色相
+fn clamp_luminance(c: Color, limit: Option<f64>) -> Color {
+    match limit {
+        Some(limit) if color::luminance(c) > limit => c * (limit / color::luminance(c)),
+        _ => c,
+    }
+}
+
+impl Integrator {
+    // 接受一个光线做为参数 然后计算这条光线所产生的颜色
+    // 固定深度时 max_depth 为最大反射次数
+    // 直接光与间接光分别限制亮度 用少量偏差换掉亮斑
+    pub fn ray_color(&self, ray: Ray, max_depth: i32, sampler: &mut dyn Sampler) -> Color {
+        let radiance = self.trace(ray, max_depth, sampler);
+        clamp_luminance(radiance.direct, self.clamp_direct)
+            + clamp_luminance(radiance.indirect, self.clamp_indirect)
+    }
+
+    // 光源与环境光合成的分布 两者都不能采样时为 None
+    fn light_pdf(&self, origin: Vec3) -> Option<MixturePdf> {
+        let mut p: Vec<(f64, Box<dyn Pdf + '_>)> = vec![];
+        if !self.lights.objects.is_empty() {
+            p.push((1.0, Box::new(HittablePdf::new(&self.lights, origin))));
+        }
+        if self.environment.can_sample() {
+            p.push((1.0, Box::new(EnvironmentPdf::new(&self.environment))));
+        }
+        if p.is_empty() {
+            None
+        } else {
+            Some(MixturePdf::new(p))
+        }
+    }
+
+    // 材质采样得到的光线碰到光源时的权重
+    // bsdf_pdf 为 None 表示这条光线不参与多重重要性采样
+    fn mis_weight(&self, ray: Ray, bsdf_pdf: Option<f64>) -> f64 {
+        match (bsdf_pdf, self.light_pdf(ray.origin)) {
+            (Some(bsdf_pdf), Some(light_pdf)) => {
+                power_heuristic(bsdf_pdf, light_pdf.value(ray.direction))
+            }
+            _ => 1.0,
+        }
+    }
+
+    // 光线直接看到的自发光 不再继续反射
+    fn emitted_along(&self, ray: Ray, sampler: &mut dyn Sampler) -> Color {
+        stats::count(Counter::ShadowRays);
+        match self.world.hit(ray, 0.0001, f64::INFINITY, sampler) {
+            Some(hit_record) => {
+                hit_record
+                    .material
+                    .emitted(hit_record.u, hit_record.v, hit_record.point)
+            }
+            None => self.environment.value(ray.direction, ray.origin),
+        }
+    }
+
+    fn trace(&self, mut ray: Ray, max_depth: i32, sampler: &mut dyn Sampler) -> Radiance {
+        let mut radiance = Radiance {
+            direct: Color::zero(),
+            indirect: Color::zero(),
+        };
+        // 路径上累积的衰减
+        let mut throughput = Color::one();
+        // 上一次材质采样的概率密度 为 None 表示这条光线不参与多重重要性采样
+        let mut bsdf_pdf = None;
+        let mut depth = 0;
+        loop {
+            // 固定深度时直接截断
+            if self.termination == Termination::Fixed && depth >= max_depth {
+                break;
+            }
+            if depth > 0 {
+                stats::count(Counter::BounceRays);
+            }
+            let hit_record = match self.world.hit(ray, 0.0001, f64::INFINITY, sampler) {
+                Some(hit_record) => hit_record,
+                // 不相交 则加上背景颜色
+                None => {
+                    let background = self.environment.value(ray.direction, ray.origin);
+                    if background.length_squared() > 0.0 {
+                        radiance.add(
+                            depth,
+                            throughput * background * self.mis_weight(ray, bsdf_pdf),
+                        );
+                    }
+                    break;
+                }
+            };
+            // 调用不同材质产生不同的反射
+            let emitted = hit_record
+                .material
+                .emitted(hit_record.u, hit_record.v, hit_record.point);
+            if emitted.length_squared() > 0.0 {
+                radiance.add(depth, throughput * emitted * self.mis_weight(ray, bsdf_pdf));
+            }
+            let (attenuation, pdf) = match hit_record.material.scatter(&ray, &hit_record, sampler) {
+                None => break,
+                Some(ScatterRecord::Specular {
+                    attenuation,
+                    ray: scattered,
+                }) => {
+                    throughput = throughput * attenuation;
+                    ray = scattered;
+                    bsdf_pdf = None;
+                    depth += 1;
+                    if !self.survive(&mut throughput, depth, sampler) {
+                        break;
+                    }
+                    continue;
+                }
+                Some(ScatterRecord::Diffuse { attenuation, pdf }) => (attenuation, pdf),
+            };
+            let point = hit_record.point;
+            let scattering = |direction: Vec3| {
+                let scattered = Ray::new(point, direction, ray.time);
+                let scattering_pdf =
+                    hit_record
+                        .material
+                        .scattering_pdf(&ray, &hit_record, &scattered);
+                (scattered, scattering_pdf)
+            };
+            let (direction, pdf_value) = match self.sampling {
+                Sampling::Bsdf => {
+                    let direction = pdf.generate(sampler);
+                    bsdf_pdf = None;
+                    (direction, pdf.value(direction))
+                }
+                Sampling::Light => {
+                    // 材质分布与光源分布各占一半
+                    let mut p: Vec<(f64, Box<dyn Pdf + '_>)> = vec![(1.0, pdf)];
+                    if let Some(light_pdf) = self.light_pdf(point) {
+                        p.push((1.0, Box::new(light_pdf)));
+                    }
+                    let mixture = MixturePdf::new(p);
+                    let direction = mixture.generate(sampler);
+                    bsdf_pdf = None;
+                    (direction, mixture.value(direction))
+                }
+                Sampling::Mis => {
+                    // 朝光源采样一次 得到直接光照
+                    // 固定深度下最后一层的材质采样无法继续 这里也不再朝光源采样
+                    if self.termination == Termination::Roulette || depth + 1 < max_depth {
+                        if let Some(light_pdf) = self.light_pdf(point) {
+                            let direction = light_pdf.generate(sampler);
+                            let light_value = light_pdf.value(direction);
+                            let (shadow, scattering_pdf) = scattering(direction);
+                            if light_value > 0.0 && scattering_pdf > 0.0 {
+                                let weight = power_heuristic(light_value, pdf.value(direction));
+                                radiance.add(
+                                    depth + 1,
+                                    throughput
+                                        * attenuation
+                                        * scattering_pdf
+                                        * self.emitted_along(shadow, sampler)
+                                        * weight
+                                        / light_value,
+                                );
+                            }
+                        }
+                    }
+                    // 再按材质采样一次 碰到光源时按权重计入
+                    let direction = pdf.generate(sampler);
+                    let pdf_value = pdf.value(direction);
+                    bsdf_pdf = Some(pdf_value);
+                    (direction, pdf_value)
+                }
+            };
+            if pdf_value <= 0.0 {
+                break;
+            }
+            let (scattered, scattering_pdf) = scattering(direction);
+            throughput = throughput * attenuation * scattering_pdf / pdf_value;
+            ray = scattered;
+            depth += 1;
+            if !self.survive(&mut throughput, depth, sampler) {
+                break;
+            }
+        }
+        radiance
+    }
+
+    // 俄罗斯轮盘赌 存活的路径按概率放大以保持无偏
+    // 存活概率不超过 0.95 保证路径总会结束
+    fn survive(&self, throughput: &mut Color, depth: i32, sampler: &mut dyn Sampler) -> bool {
+        if self.termination == Termination::Fixed || depth < self.rr_depth {
+            return true;
+        }
+        let p = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+        if sampler.gen::<f64>() >= p {
+            return false;
+        }
+        *throughput /= p;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::XZRect;
+    use crate::cli::SamplerType;
+    use crate::material::{DiffuseLight, Metal};
+    use crate::sampler;
+    use crate::texture::SolidColor;
+    use std::sync::Arc;
+
+    // 粗糙金属平面反射一个很小的光源 返回像素值的均值与方差
+    fn glossy_highlight(sampling: Sampling) -> (f64, f64) {
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        let metal = Metal::new(Color::new(0.9, 0.9, 0.9), 0.3);
+        world.add(Box::new(XZRect::new(-20.0, 20.0, -20.0, 20.0, 0.0, metal)));
+        let light = XZRect::new(
+            4.9,
+            5.1,
+            -0.1,
+            0.1,
+            5.0,
+            DiffuseLight::new(SolidColor::new(Color::new(500.0, 500.0, 500.0))),
+        );
+        world.add(Box::new(light));
+        lights.add(Box::new(light));
+        let integrator = Integrator {
+            world,
+            lights,
+            environment: Environment::new(Arc::new(SolidColor::new(Color::zero()))),
+            sampling,
+            termination: Termination::Fixed,
+            rr_depth: 3,
+            clamp_direct: None,
+            clamp_indirect: None,
+        };
+        let n = 4000;
+        let ray = Ray::new(Vec3::new(-3.0, 3.0, 0.0), Vec3::new(1.0, -1.0, 0.0), 0.0);
+        let values: Vec<f64> = (0..n)
+            .map(|k| {
+                let mut sampler = sampler::create(SamplerType::Independent, 3, 0, k, n);
+                color::luminance(integrator.ray_color(ray, 2, sampler.as_mut()))
+            })
+            .collect();
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        (mean, variance)
+    }
+
+    #[test]
+    fn mis_lowers_variance_on_glossy_metal() {
+        let (bsdf_mean, bsdf_variance) = glossy_highlight(Sampling::Bsdf);
+        let (mis_mean, mis_variance) = glossy_highlight(Sampling::Mis);
+        // 同样的采样数 两者的期望相同 多重重要性采样的方差明显更小
+        let error = ((bsdf_variance + mis_variance) / 4000.0).sqrt();
+        assert!(
+            (bsdf_mean - mis_mean).abs() < 4.0 * error,
+            "{} vs {}",
+            bsdf_mean,
+            mis_mean
+        );
+        assert!(
+            mis_variance * 4.0 < bsdf_variance,
+            "{} vs {}",
+            mis_variance,
+            bsdf_variance
+        );
+    }
+}
@@ -0,0 +1,55 @@
+// 中断
+// 第一次收到 SIGINT 或 SIGTERM 时请求停止 线程渲染完手上的块后结束 第二次直接退出
+// 退出码按惯例为 128 加信号编号 即 SIGINT 为 130 SIGTERM 为 143
+
+use std::sync::atomic::{AtomicI32, Ordering};
+
+// 第一次收到的信号 0 表示没有
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+const MESSAGE: &[u8] = b"\nStopping after the current tiles, interrupt again to abort\n";
+
+// 信号处理函数中只做异步信号安全的操作
+extern "C" fn handle(signal: libc::c_int) {
+    if SIGNAL
+        .compare_exchange(0, signal, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+    {
+        unsafe {
+            libc::write(
+                libc::STDERR_FILENO,
+                MESSAGE.as_ptr() as *const libc::c_void,
+                MESSAGE.len(),
+            );
+        }
+    } else {
+        unsafe { libc::_exit(128 + signal) };
+    }
+}
+
+// 程序启动时安装 加载场景与建树时中断也会保留已有的结果
+// 用 sigaction 而不是语义因平台而异的 signal SA_RESTART 让被打断的系统调用自动重试
+pub fn install() {
+    for &signal in &[libc::SIGINT, libc::SIGTERM] {
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        assert!(result == 0, "Cannot set the signal handler");
+    }
+}
+
+pub fn requested() -> bool {
+    SIGNAL.load(Ordering::SeqCst) != 0
+}
+
+// 被信号中断时的退出码 否则为 0
+pub fn exit_code() -> i32 {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => 0,
+        signal => 128 + signal,
+    }
+}
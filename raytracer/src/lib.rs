@@ -0,0 +1,34 @@
+// 渲染器的全部模块 供 raytracer 与 denoise 两个程序使用
+
+pub mod aabb;
+pub mod aov;
+pub mod bvh_node;
+pub mod camera;
+pub mod checkpoint;
+pub mod cli;
+pub mod color;
+pub mod denoise;
+pub mod environment;
+pub mod film;
+pub mod filter;
+pub mod hittable;
+pub mod integrator;
+pub mod interrupt;
+pub mod linear_bvh;
+pub mod material;
+pub mod matrix;
+pub mod mc_world;
+pub mod obj_file;
+pub mod onb;
+pub mod output;
+pub mod pdf;
+pub mod ray;
+pub mod render;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod stats;
+pub mod texture;
+pub mod tonemap;
+pub mod vec3;
+use hittable::*;
@@ -0,0 +1,191 @@
+// 展开成数组的BVH
+// 与 BVHNode 用同样的 SAH 划分 节点按深度优先顺序存放在一个连续的数组中
+// 左孩子紧跟在父节点之后 只需记下右孩子的位置 叶子记下物体的起点与个数
+// 遍历时先进入光线方向上较近的孩子 找到交点后缩短 t_max 远处的子树大多不必再测试
+
+use crate::aabb::Aabb;
+use crate::bvh_node::{self, BuildNode, Tally};
+use crate::hittable::*;
+use crate::hittable_list::HittableList;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::stats::{self, Counter};
+use crate::vec3::Vec3;
+use std::time::Instant;
+
+// 遍历栈的容量 栈中最多同时有树的深度那么多个节点
+// SAH 只划分到 MAX_SAH_DEPTH 层 之后按中位数对半分 物体数不超过 u32 的范围 再多 32 层就到叶子
+const STACK_SIZE: usize = bvh_node::MAX_SAH_DEPTH + 33;
+
+#[derive(Copy, Clone)]
+struct LinearNode {
+    bounding_box: Aabb,
+    // 叶子为第一个物体的下标 内部节点为右孩子的下标
+    offset: u32,
+    // 叶子中的物体数 内部节点为 0
+    count: u16,
+    // 内部节点的划分轴
+    axis: u8,
+}
+
+pub struct LinearBVH {
+    nodes: Vec<LinearNode>,
+    objects: Vec<Box<dyn Hittable>>,
+}
+
+impl LinearBVH {
+    pub fn new(hittable_list: HittableList, time0: f64, time1: f64) -> Self {
+        let mut bvh = Self {
+            nodes: vec![],
+            objects: Vec::with_capacity(hittable_list.objects.len()),
+        };
+        if hittable_list.objects.is_empty() {
+            return bvh;
+        }
+        let start = Instant::now();
+        let mut tally = Tally::default();
+        let primitives = bvh_node::primitives(hittable_list, time0, time1);
+        let root = bvh_node::build(primitives, 0, &mut tally);
+        debug_assert!(tally.max_depth < STACK_SIZE, "[BVH] tree too deep");
+        bvh.nodes.reserve(tally.nodes + tally.leaves);
+        bvh.flatten(root);
+        tally.record(&bvh.nodes[0].bounding_box, start.elapsed());
+        bvh
+    }
+
+    // 返回节点的下标
+    fn flatten(&mut self, node: BuildNode) -> usize {
+        let index = self.nodes.len();
+        match node {
+            BuildNode::Leaf(bounding_box, objects) => {
+                self.nodes.push(LinearNode {
+                    bounding_box,
+                    offset: self.objects.len() as u32,
+                    count: objects.len() as u16,
+                    axis: 0,
+                });
+                self.objects.extend(objects);
+            }
+            BuildNode::Inner(bounding_box, axis, left, right) => {
+                self.nodes.push(LinearNode {
+                    bounding_box,
+                    offset: 0,
+                    count: 0,
+                    axis: axis as u8,
+                });
+                self.flatten(*left);
+                self.nodes[index].offset = self.flatten(*right) as u32;
+            }
+        }
+        index
+    }
+}
+
+impl Hittable for LinearBVH {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_direction = Vec3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        let negative = [
+            inv_direction.x < 0.0,
+            inv_direction.y < 0.0,
+            inv_direction.z < 0.0,
+        ];
+        let mut stack = [0; STACK_SIZE];
+        let mut top = 0;
+        let mut index = 0;
+        let mut closest = t_max;
+        let mut result = None;
+        loop {
+            stats::count(Counter::BvhNodes);
+            let node = &self.nodes[index];
+            if node
+                .bounding_box
+                .hit_inv(ray.origin, inv_direction, t_min, closest)
+            {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for object in &self.objects[first..first + node.count as usize] {
+                        if let Some(hit_record) = object.hit(ray, t_min, closest, sampler) {
+                            closest = hit_record.t;
+                            result = Some(hit_record);
+                        }
+                    }
+                } else {
+                    // 方向为负时右孩子在前
+                    let (near, far) = if negative[node.axis as usize] {
+                        (node.offset as usize, index + 1)
+                    } else {
+                        (index + 1, node.offset as usize)
+                    };
+                    stack[top] = far;
+                    top += 1;
+                    index = near;
+                    continue;
+                }
+            }
+            if top == 0 {
+                break;
+            }
+            top -= 1;
+            index = stack[top];
+        }
+        result
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.bounding_box)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::SamplerType;
+    use crate::material::Lambertian;
+    use crate::sampler;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+
+    // 沿 x 轴的距离依次翻倍 SAH 建出的树很深
+    fn spheres() -> HittableList {
+        let material = Lambertian::new(SolidColor::new(Vec3::one()));
+        let mut list = HittableList::new();
+        for k in 0..300 {
+            let center = Vec3::new(2.0_f64.powi(k), 0.0, 0.0);
+            list.add(Box::new(Sphere::new(center, 0.1, material)));
+        }
+        list
+    }
+
+    fn assert_same_hits(bvh: &LinearBVH) {
+        let list = spheres();
+        let mut sampler = sampler::create(SamplerType::Independent, 0, 0, 0, 1);
+        for k in 0..300 {
+            let origin = Vec3::new(2.0_f64.powi(k), 1.0, 0.0);
+            for direction in [Vec3::new(0.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0)] {
+                let ray = Ray::new(origin, direction, 0.0);
+                let expected = list.hit(ray, 0.001, f64::INFINITY, sampler.as_mut());
+                let actual = bvh.hit(ray, 0.001, f64::INFINITY, sampler.as_mut());
+                assert_eq!(expected.map(|h| h.t), actual.map(|h| h.t));
+            }
+        }
+    }
+
+    #[test]
+    fn deep_tree_finds_the_closest_hit() {
+        let bvh = LinearBVH::new(spheres(), 0.0, 1.0);
+        assert_same_hits(&bvh);
+    }
+}
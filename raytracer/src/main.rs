@@ -1,82 +1,128 @@
-mod aabb;
-mod bvh_node;
-mod camera;
-mod hittable;
-mod material;
-mod mc_world;
-mod obj_file;
-mod ray;
-mod texture;
-mod vec3;
-use crate::bvh_node::BVHNode;
-use crate::mc_world::Block;
-use crate::texture::*;
-use camera::Camera;
+use clap::{Parser, ValueEnum};
 use console::style;
-use hittable::*;
-use hittable_list::HittableList;
-use image::{ImageBuffer, RgbImage};
 use indicatif::{ProgressBar, ProgressStyle};
-use rand::Rng;
-use ray::Ray;
-use std::sync::Arc;
-use std::thread;
-use std::{fs::File, process::exit};
-use texture::Texture;
-use vec3::{Color, Vec3};
+use raytracer::camera::Camera;
+use raytracer::checkpoint::{self, CheckpointInfo};
+use raytracer::cli::{Aov, Args, Estimator, ToneMapper};
+use raytracer::environment::Environment;
+use raytracer::integrator::Integrator;
+use raytracer::render::{self, Accumulator, RenderSettings};
+use raytracer::scene::{self, Scene};
+use raytracer::stats::RenderStats;
+use raytracer::tonemap::ToneMapping;
+use raytracer::vec3::Vec3;
+use raytracer::{aov, bvh_node, color, denoise, filter, interrupt, output, scene_file};
+use std::process::exit;
+use std::time::{Duration, Instant};
 
-// 接受一个光线做为参数 然后计算这条光线所产生的颜色
-fn ray_color(
-    ray: Ray,
-    background: &Arc<dyn Texture>,
-    world: &Arc<HittableList>,
-    depth: i32,
-) -> Color {
-    // 限制递归层数
-    if depth <= 0 {
-        return Color::zero();
+fn main() {
+    let args = Args::parse();
+    interrupt::install();
+    if args.list_scenes {
+        for (name, description) in scene::SCENES.iter() {
+            println!("{:<20} {}", style(name).yellow(), description);
+        }
+        exit(0);
+    }
+
+    // 线程池 默认使用全部核心
+    // 读取图片纹理时也会用到 需要在加载场景之前建立
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .build_global()
+            .expect("Cannot build the thread pool");
     }
-    // 调用不同材质产生不同的反射
-    if let Some(hit_record) = world.hit(ray, 0.0001, f64::INFINITY) {
-        let emitted = hit_record
-            .material
-            .emitted(hit_record.u, hit_record.v, hit_record.point);
-        if let Some((attenuation, scattered)) = hit_record.material.scatter(&ray, &hit_record) {
-            return emitted + attenuation * ray_color(scattered, background, world, depth - 1);
-        } else {
-            return emitted;
+
+    // 工作空间 加载场景时的颜色转换依赖它
+    color::set_working_space(args.working_space);
+    // 建树方法 同样需要在加载场景之前设定
+    bvh_node::set_builder(args.bvh);
+
+    // 色调映射 可以直接作用于已有的线性图像
+    let tone = ToneMapping::new(args.exposure, args.white_balance, args.tonemap);
+    if let Some(input) = &args.tonemap_from {
+        let film = match output::read(input) {
+            Ok(film) => film,
+            Err(err) => {
+                println!(
+                    "{} \"{}\": {}",
+                    style("Cannot read image").red(),
+                    input.display(),
+                    err
+                );
+                exit(1);
+            }
+        };
+        println!(
+            "Ouput image as \"{}\"",
+            style(args.output.to_str().unwrap()).yellow()
+        );
+        if let Err(err) = output::write(&film, &args.output, &tone) {
+            println!("{} {}", style("Outputting image fails.").red(), err);
+            exit(1);
         }
+        exit(0);
     }
-    // 不相交 则返回背景颜色
-    let unit_dir = Vec3::unit_vector(ray.direction);
-    let theta = unit_dir.y.acos();
-    let phi = (-unit_dir.z).atan2(unit_dir.x) + std::f64::consts::PI;
-    background.value(
-        phi / (2.0 * std::f64::consts::PI),
-        theta / std::f64::consts::PI,
-        ray.origin,
-    )
-}
 
-fn main() {
     // 图像
-    let aspect_ratio = 3.0 / 2.0;
-    let width = 600;
-    let height = (width as f64 / aspect_ratio) as u32;
-    let samples_per_pixel = 180;
-    let max_depth = 25;
+    let width = args.width;
+    let height = args.height.unwrap_or((width as f64 / (3.0 / 2.0)) as u32);
+    let aspect_ratio = width as f64 / height as f64;
+    let samples_per_pixel = args.spp;
+    let max_depth = args.max_depth;
+
+    // 世界
+    let scene = match &args.scene_file {
+        Some(file) => match scene_file::load(file, args.seed) {
+            Ok(scene) => scene,
+            Err(err) => {
+                println!(
+                    "{} \"{}\": {}",
+                    style("Cannot load scene file").red(),
+                    file.display(),
+                    err
+                );
+                exit(1);
+            }
+        },
+        None => match Scene::load(&args.scene, args.seed) {
+            Some(scene) => scene,
+            None => {
+                println!(
+                    "{} \"{}\", try --list-scenes",
+                    style("Unknown scene").red(),
+                    args.scene
+                );
+                exit(1);
+            }
+        },
+    };
+    // 加载期间被中断 还没有新的采样 已有的断点保持不变
+    if interrupt::requested() {
+        println!("Interrupted while loading the scene");
+        exit(interrupt::exit_code());
+    }
+    let integrator = Integrator {
+        world: scene.world,
+        lights: scene.lights,
+        environment: Environment::new(scene.background),
+        sampling: args.sampling,
+        termination: args.termination,
+        rr_depth: args.rr_depth,
+        clamp_direct: args.clamp_direct,
+        clamp_indirect: args.clamp_indirect,
+    };
 
     // 生成
-    let path = std::path::Path::new("output/objtest/image4.jpg");
-    let prefix = path.parent().unwrap();
-    std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
-    let quality = 100;
-    let mut img_pre: RgbImage = ImageBuffer::new(width / 4, height / 4);
-    let mut img: RgbImage = ImageBuffer::new(width, height);
+    let path = args.output.as_path();
+    if let Some(prefix) = path.parent() {
+        std::fs::create_dir_all(prefix).expect("Cannot create all the parents");
+    }
     let progress = if option_env!("CI").unwrap_or_default() == "true" {
         ProgressBar::hidden()
     } else {
-        ProgressBar::new(width as u64)
+        ProgressBar::new(0)
     };
     progress.set_style(
         ProgressStyle::default_bar()
@@ -86,288 +132,277 @@ fn main() {
             .progress_chars("#>-"),
     );
 
-    // 世界
-    let world_type = 0;
-    let lookfrom: Vec3;
-    let lookat: Vec3;
-    let vfov: f64;
-    let aperture: f64;
-    let background: Arc<dyn Texture>;
-    let world: HittableList;
-    match world_type {
-        1 => {
-            world = HittableList {
-                objects: vec![BVHNode::create(generator::random_scene(), 0.0, 1.0)],
-            };
-            // background = Arc::new(SolidColor::new(Color::new(0.7, 0.8, 1.0)));
-            background = Arc::new(HdrImageTexture::new(
-                "raytracer/src/texture/img/Path_Env.hdr".to_string(),
-                0.7,
-            ));
-            lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.1;
-        }
-        2 => {
-            world = HittableList {
-                objects: vec![BVHNode::create(generator::two_spheres(), 0.0, 1.0)],
-            };
-            background = Arc::new(SolidColor::new(Color::new(0.7, 0.8, 1.0)));
-            lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.0;
-        }
-        3 => {
-            world = HittableList {
-                objects: vec![BVHNode::create(generator::two_perlin_spheres(), 0.0, 1.0)],
-            };
-            background = Arc::new(SolidColor::new(Color::new(0.7, 0.8, 1.0)));
-            lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.0;
-        }
-        4 => {
-            world = HittableList {
-                objects: vec![BVHNode::create(generator::earth(), 0.0, 1.0)],
-            };
-            background = Arc::new(SolidColor::new(Color::new(0.7, 0.8, 1.0)));
-            lookfrom = Vec3::new(13.0, 2.0, 3.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.0;
-        }
-        5 => {
-            world = HittableList {
-                objects: vec![BVHNode::create(generator::simple_light(), 0.0, 1.0)],
-            };
-            background = Arc::new(SolidColor::new(Color::new(0.0, 0.0, 0.0)));
-            lookfrom = Vec3::new(26.0, 3.0, 6.0);
-            lookat = Vec3::new(0.0, 2.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.0;
-        }
-        6 => {
-            world = HittableList {
-                objects: vec![BVHNode::create(generator::cornell_box(), 0.0, 1.0)],
-            };
-            background = Arc::new(SolidColor::new(Color::new(0.0, 0.0, 0.0)));
-            lookfrom = Vec3::new(278.0, 278.0, -800.0);
-            lookat = Vec3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
-            aperture = 0.0;
-        }
-        7 => {
-            world = HittableList {
-                objects: vec![BVHNode::create(generator::cornell_smoke(), 0.0, 1.0)],
-            };
-            background = Arc::new(SolidColor::new(Color::new(0.0, 0.0, 0.0)));
-            lookfrom = Vec3::new(278.0, 278.0, -800.0);
-            lookat = Vec3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
-            aperture = 0.0;
-        }
-        8 => {
-            world = generator::final_scene();
-            background = Arc::new(SolidColor::new(Color::new(0.0, 0.0, 0.0)));
-            lookfrom = Vec3::new(478.0, 278.0, -600.0);
-            lookat = Vec3::new(278.0, 278.0, 0.0);
-            vfov = 40.0;
-            aperture = 0.0;
-        }
-        9 => {
-            world = generator::triangles();
-            background = Arc::new(SolidColor::new(Color::new(0.7, 0.8, 1.0)));
-            // background = Color::new(0.0, 0.0, 0.0);
-            lookfrom = Vec3::new(0.0, 0.0, 10.0);
-            lookat = Vec3::new(0.0, 0.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.0;
-        }
-        10 => {
-            world = generator::obj_cat();
-            // background = SolidColor::new(Color::new(0.7, 0.8, 1.0));
-            background = Arc::new(ImageTexture::new(
-                "raytracer/src/texture/img/earthmap.jpg".to_string(),
-            ));
-            lookfrom = Vec3::new(1000.0, 500.0, 1000.0);
-            lookat = Vec3::new(0.0, 200.0, 0.0);
-            vfov = 20.0;
-            aperture = 0.0;
-        }
-        _ => {
-            world = Block::the_world();
-            // background = Arc::new(SolidColor::new(Color::new(0.7, 0.8, 1.0)));
-            // background = Color::new(0.0, 0.0, 0.0);
-            background = Arc::new(ImageTexture::new(
-                "raytracer/src/texture/img/twilight.jpg".to_string(),
-            ));
-            lookfrom = Vec3::new(10.0, 10.0, 10.0);
-            lookat = Vec3::new(36.0, 2.0, 36.0);
-            vfov = 40.0;
-            aperture = 0.2;
-        }
-    };
-
     // 镜头
     let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 40.0;
     let cam = Camera::new(
-        (lookfrom, lookat),
+        (
+            args.lookfrom.unwrap_or(scene.lookfrom),
+            args.lookat.unwrap_or(scene.lookat),
+        ),
         vup,
-        vfov,
+        args.vfov.unwrap_or(scene.vfov),
         aspect_ratio,
-        aperture,
-        dist_to_focus,
+        args.aperture.unwrap_or(scene.aperture),
+        args.focus_dist.unwrap_or(scene.focus_dist),
         (0.0, 1.0),
     );
 
-    // 线程
-    let thread_count = 7;
-    let mul_cam = Arc::new(cam);
-    let mul_world = Arc::new(world);
-    let mul_progress = Arc::new(progress);
-    let mut handles_pre = vec![];
-
-    for i in 0..thread_count {
-        let mut curr = i;
-        let step = thread_count;
-        let tot_width = width / 4;
-        let tot_height = height / 4;
-        let cur_samples_per_pixel = (samples_per_pixel / 8).max(1);
-        let cur_max_depth = max_depth / 2;
-        let thread_cam = Arc::clone(&mul_cam);
-        let thread_world = Arc::clone(&mul_world);
-        let thread_background = Arc::clone(&background);
+    // 高质量渲染 分轮累计采样 可以从断点继续
+    let settings = RenderSettings {
+        width,
+        height,
+        samples_per_pixel,
+        max_depth,
+        seed: args.seed,
+        sampler: args.sampler,
+        error_threshold: args.adaptive_threshold,
+        min_samples: args.min_spp,
+        filter: filter::create(args.filter, args.filter_radius),
+    };
+    // 中位数估计把采样分组累加
+    let buckets = match args.estimator {
+        Estimator::Mean => 1,
+        Estimator::MedianOfMeans => args.mom_buckets.max(1),
+    };
+    let info = CheckpointInfo {
+        width,
+        height,
+        sampler: args.sampler,
+        seed: args.seed,
+        samples_per_pixel,
+        filter: args.filter,
+        filter_radius: settings.filter.radius(),
+        buckets,
+    };
+    let mut accumulator = match &args.resume {
+        Some(file) => match checkpoint::load(file) {
+            Ok((saved, accumulator)) if saved.resumable_as(&info) => accumulator,
+            Ok(_) => {
+                println!(
+                    "{} \"{}\": size, sampler, seed, filter, estimator or stratified spp differ from this render",
+                    style("Cannot resume from checkpoint").red(),
+                    file.display()
+                );
+                exit(1);
+            }
+            Err(err) => {
+                println!(
+                    "{} \"{}\": {}",
+                    style("Cannot resume from checkpoint").red(),
+                    file.display(),
+                    err
+                );
+                exit(1);
+            }
+        },
+        None => Accumulator::new(width, height, buckets),
+    };
+    let checkpoint_path = args.checkpoint.as_ref().or(args.resume.as_ref());
+    let save_checkpoint = |accumulator: &Accumulator| {
+        if let Some(file) = checkpoint_path {
+            if let Err(err) = checkpoint::save(file, &info, accumulator) {
+                println!("{} {}", style("Saving checkpoint fails.").red(), err);
+            }
+        }
+    };
 
-        // 生成新线程
-        let handle_pre = thread::spawn(move || {
-            let mut rng = rand::thread_rng();
-            let mut result = vec![];
-            while curr < tot_width {
-                let mut col = vec![];
-                for j in 0..tot_height {
-                    let mut pixel_color = Vec3::zero();
-                    for _ in 0..cur_samples_per_pixel {
-                        // x,y方向分量 加入了多重采样抗锯齿
-                        let u_rand: f64 = rng.gen();
-                        let v_rand: f64 = rng.gen();
-                        let u: f64 = (curr as f64 + u_rand) / ((tot_width - 1) as f64);
-                        let v: f64 = (j as f64 + v_rand) / ((tot_height - 1) as f64);
+    let preview_path = args
+        .preview
+        .clone()
+        .unwrap_or_else(|| output::sibling(path, "preview"));
 
-                        // 生成光线
-                        let ray = thread_cam.get_ray(u, v);
-                        pixel_color +=
-                            ray_color(ray, &thread_background, &thread_world, cur_max_depth);
-                    }
-                    let rgb = (pixel_color / cur_samples_per_pixel as f64).to_u8();
-                    col.push(rgb);
-                }
-                result.push((curr, col));
-                curr += step;
-            }
-            // 返回结果
-            result
+    let start = Instant::now();
+    let deadline = args
+        .time_limit
+        .map(|minutes| start + Duration::from_secs_f64(minutes * 60.0));
+    // 第一轮只渲染预览所需的采样 之后每轮加 pass_spp 个
+    let pass_spp = args.pass_spp.max(1);
+    let first = if args.no_preview {
+        0
+    } else {
+        args.preview_spp.min(samples_per_pixel)
+    };
+    let passes = (samples_per_pixel - first + pass_spp - 1) / pass_spp;
+    let targets: Vec<u32> = Some(first)
+        .filter(|&first| first > 0)
+        .into_iter()
+        .chain((1..=passes).map(|pass| (first + pass * pass_spp).min(samples_per_pixel)))
+        .collect();
+    progress.set_length((targets.len() * render::tiles(width, height).len()) as u64);
+    // 收到中断信号或超时后 渲染完手上的块就停下
+    let stop = || interrupt::requested() || deadline.map_or(false, |d| Instant::now() >= d);
+    let mut last_checkpoint = start;
+    let mut last_preview = None;
+    let mut stopped = None;
+    let mut stats = RenderStats::new(rayon::current_num_threads());
+    stats.bvh = bvh_node::quality();
+    for target in targets {
+        let complete = render::render_pass(
+            &integrator,
+            &cam,
+            &settings,
+            &mut accumulator,
+            target,
+            &stop,
+            &progress,
+            &mut stats,
+        );
+        if !complete {
+            let (reason, message) = if interrupt::requested() {
+                ("interrupted", "Interrupted")
+            } else {
+                ("time limit", "Time limit reached")
+            };
+            progress.println(format!("{}, keeping the samples rendered so far", message));
+            stopped = Some(reason);
+            break;
+        }
+        // 预览复用已有的采样 缩小后写到单独的文件
+        let due = last_preview.map_or(true, |t: Instant| {
+            t.elapsed().as_secs_f64() >= args.preview_interval
         });
-        handles_pre.push(handle_pre);
-    }
-
-    // 接收结果并汇总
-    let results_pre: Vec<_> = handles_pre.into_iter().map(|h| h.join().unwrap()).collect();
-    for result in &results_pre {
-        for (col_name, col_rgb) in result {
-            for (i, pix) in col_rgb.iter().enumerate() {
-                let pixel = img_pre.get_pixel_mut(*col_name, height / 4 - 1 - i as u32);
-                *pixel = image::Rgb([pix.0, pix.1, pix.2]);
+        if !args.no_preview && due {
+            let preview = accumulator.to_film().downsample(args.preview_scale.max(1));
+            if last_preview.is_none() {
+                progress.println(format!(
+                    "Preview image as \"{}\"",
+                    style(preview_path.to_str().unwrap()).yellow()
+                ));
+            }
+            if let Err(err) = output::write(&preview, &preview_path, &tone) {
+                progress.println(format!(
+                    "{} {}",
+                    style("Outputting preview fails.").red(),
+                    err
+                ));
             }
+            last_preview = Some(Instant::now());
+        }
+        if last_checkpoint.elapsed().as_secs_f64() >= args.checkpoint_interval {
+            save_checkpoint(&accumulator);
+            last_checkpoint = Instant::now();
         }
     }
+    progress.finish();
+    save_checkpoint(&accumulator);
+    let mut img = accumulator.to_film();
+    // 写入 .exr 文件头的渲染设置 采样数是实际完成的
+    img.metadata = img.sample_metadata(samples_per_pixel, stopped);
+    img.metadata.extend(
+        vec![
+            ("sampler", value_name(args.sampler)),
+            ("seed", args.seed.to_string()),
+            ("filter", value_name(args.filter)),
+            ("filterRadius", settings.filter.radius().to_string()),
+            ("clampDirect", limit(args.clamp_direct)),
+            ("clampIndirect", limit(args.clamp_indirect)),
+            ("estimator", value_name(args.estimator)),
+            ("estimatorBuckets", buckets.to_string()),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value)),
+    );
 
     println!(
-        "Preview image as \"{}\"",
+        "Ouput image as \"{}\"",
         style(path.to_str().unwrap()).yellow()
     );
-    let output_image_pre = image::DynamicImage::ImageRgb8(img_pre);
-    let mut output_file_pre = File::create(path).unwrap();
-    match output_image_pre.write_to(
-        &mut output_file_pre,
-        image::ImageOutputFormat::Jpeg(quality),
-    ) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputting image fails.").red()),
+    // 第一次碰撞的输出变量 .exr 时作为图层一起写入
+    // 降噪需要反照率与法线 没有要求输出时也要渲染
+    let mut wanted = args.aov.clone();
+    if args.denoise {
+        for aov in [Aov::Albedo, Aov::Normal] {
+            if !wanted.contains(&aov) {
+                wanted.push(aov);
+            }
+        }
     }
-
-    // 高质量渲染
-    let mut handles = vec![];
-    for i in 0..thread_count {
-        let mut curr = i;
-        let step = thread_count;
-        let tot_width = width;
-        let tot_height = height;
-        let cur_samples_per_pixel = samples_per_pixel;
-        let cur_max_depth = max_depth;
-        let thread_cam = Arc::clone(&mul_cam);
-        let thread_world = Arc::clone(&mul_world);
-        let thread_background = Arc::clone(&background);
-        let cur_progress = Arc::clone(&mul_progress);
-
-        // 生成新线程
-        let handle = thread::spawn(move || {
-            let mut rng = rand::thread_rng();
-            let mut result = vec![];
-            while curr < tot_width {
-                let mut col = vec![];
-                for j in 0..tot_height {
-                    let mut pixel_color = Vec3::zero();
-                    for _ in 0..cur_samples_per_pixel {
-                        // x,y方向分量 加入了多重采样抗锯齿
-                        let u_rand: f64 = rng.gen();
-                        let v_rand: f64 = rng.gen();
-                        let u: f64 = (curr as f64 + u_rand) / ((tot_width - 1) as f64);
-                        let v: f64 = (j as f64 + v_rand) / ((tot_height - 1) as f64);
-
-                        // 生成光线
-                        let ray = thread_cam.get_ray(u, v);
-                        pixel_color +=
-                            ray_color(ray, &thread_background, &thread_world, cur_max_depth);
-                    }
-                    let rgb = (pixel_color / cur_samples_per_pixel as f64).to_u8();
-                    col.push(rgb);
-                }
-                cur_progress.inc(1);
-                result.push((curr, col));
-                curr += step;
+    let mut aovs = if wanted.is_empty() {
+        vec![]
+    } else {
+        aov::render(&integrator, &cam, &settings, &wanted, args.aov_spp)
+    };
+    let img = if args.denoise {
+        let guide = |kind: Aov| &aovs.iter().find(|(aov, _)| *aov == kind).unwrap().1;
+        denoise::denoise(
+            &img,
+            guide(Aov::Albedo),
+            guide(Aov::Normal),
+            args.denoise_iterations,
+            args.denoise_strength,
+        )
+    } else {
+        img
+    };
+    aovs.retain(|(aov, _)| args.aov.contains(aov));
+    let layered = !aovs.is_empty() && output::extension(path) == "exr";
+    // 扩展名为 .exr .hdr .pfm 时保存线性数据
+    let result = if layered {
+        aov::write_layers(&img, &aovs, path)
+    } else {
+        output::write(&img, path, &tone)
+    };
+    if let Err(err) = result {
+        println!("{} {}", style("Outputting image fails.").red(), err);
+    }
+    if !layered {
+        for (aov, film) in &aovs {
+            let file = output::sibling(path, aov.name());
+            println!(
+                "AOV {} as \"{}\"",
+                aov.name(),
+                style(file.to_str().unwrap()).yellow()
+            );
+            if let Err(err) = aov::write_image(*aov, film, &file) {
+                println!("{} {}", style("Outputting image fails.").red(), err);
             }
-            // 返回结果
-            result
-        });
-        handles.push(handle);
+        }
     }
 
-    // 接收结果并汇总
-    let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
-    mul_progress.finish();
-    for result in &results {
-        for (col_name, col_rgb) in result {
-            for (i, pix) in col_rgb.iter().enumerate() {
-                let pixel = img.get_pixel_mut(*col_name, height - 1 - i as u32);
-                *pixel = image::Rgb([pix.0, pix.1, pix.2]);
-            }
+    // 采样数热度图
+    // 提前停止时每个像素实际完成的采样数可能不同
+    if args.adaptive_threshold.is_some() || stopped.is_some() {
+        println!("Average samples per pixel: {:.1}", img.average_samples());
+    }
+    if let Some(heatmap) = &args.heatmap {
+        println!(
+            "Sample heat map as \"{}\"",
+            style(heatmap.to_str().unwrap()).yellow()
+        );
+        let plain = ToneMapping::new(0.0, None, ToneMapper::Clamp);
+        if let Err(err) = output::write(&img.heatmap(samples_per_pixel), heatmap, &plain) {
+            println!("{} {}", style("Outputting image fails.").red(), err);
         }
     }
 
-    println!(
-        "Ouput image as \"{}\"",
-        style(path.to_str().unwrap()).yellow()
-    );
-    let output_image = image::DynamicImage::ImageRgb8(img);
-    let mut output_file = File::create(path).unwrap();
-    match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
-        Ok(_) => {}
-        Err(_) => println!("{}", style("Outputting image fails.").red()),
+    stats.print();
+    if let Some(file) = &args.stats_json {
+        let mut report = stats.to_json();
+        report["scene"] = match &args.scene_file {
+            Some(file) => file.display().to_string(),
+            None => args.scene.clone(),
+        }
+        .into();
+        report["width"] = width.into();
+        report["height"] = height.into();
+        report["samples_per_pixel"] = samples_per_pixel.into();
+        report["bvh"]["builder"] = value_name(args.bvh).into();
+        let result = serde_json::to_string_pretty(&report)
+            .map_err(|err| err.to_string())
+            .and_then(|json| std::fs::write(file, json + "\n").map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            println!("{} {}", style("Writing statistics fails.").red(), err);
+        }
     }
 
-    exit(0);
+    exit(interrupt::exit_code());
+}
+
+// 命令行中的取值名称
+fn value_name<T: ValueEnum>(value: T) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
+
+fn limit(value: Option<f64>) -> String {
+    value.map_or("none".to_string(), |v| v.to_string())
 }
@@ -1,50 +1,144 @@
 // 材质
 
+use crate::color;
 use crate::hittable::*;
+use crate::pdf::*;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::texture::*;
 use crate::vec3::{Color, Vec3};
 use rand::Rng;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+// 散射结果
+pub enum ScatterRecord {
+    // 镜面反射与折射 直接给出出射光线
+    Specular {
+        attenuation: Color,
+        ray: Ray,
+    },
+    // 漫反射与粗糙金属 给出出射方向的分布
+    Diffuse {
+        attenuation: Color,
+        pdf: Box<dyn Pdf>,
+    },
+}
 
 pub trait Material: Send + Sync {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)>;
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord>;
     fn emitted(&self, u: f64, v: f64, p: Vec3) -> Color;
+
+    // 非镜面材质向 scattered 方向散射的概率密度
+    fn scattering_pdf(&self, _: &Ray, _: &HitRecord, _: &Ray) -> f64 {
+        0.0
+    }
+
+    // 碰撞点的反照率 写入 AOV
+    fn albedo(&self, _: &HitRecord) -> Color {
+        Color::zero()
+    }
+
+    // 材质的编号 复制出的材质编号相同
+    fn id(&self) -> u32;
+}
+
+// 材质编号从 1 开始依次分配
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+fn next_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// 运行时确定的材质 如场景文件
+impl Material for Arc<dyn Material> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        self.as_ref().scatter(ray, hit_record, sampler)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: Vec3) -> Color {
+        self.as_ref().emitted(u, v, p)
+    }
+
+    fn scattering_pdf(&self, ray: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
+        self.as_ref().scattering_pdf(ray, hit_record, scattered)
+    }
+
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.as_ref().albedo(hit_record)
+    }
+
+    fn id(&self) -> u32 {
+        self.as_ref().id()
+    }
 }
 
 // 漫反射
 #[derive(Copy)]
 pub struct Lambertian<T: Texture> {
     pub albedo: T,
+    pub id: u32,
 }
 
 impl<T: Texture> Lambertian<T> {
     pub fn new(albedo: T) -> Self {
-        Self { albedo }
+        Self {
+            albedo,
+            id: next_id(),
+        }
     }
 }
 
 impl<T: Texture> Material for Lambertian<T> {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
-        let mut direction = hit_record.normal + Vec3::random_unit_vector();
-        if direction.near_zero() {
-            direction = hit_record.normal;
-        }
-        Some((
-            self.albedo
+    fn scatter(
+        &self,
+        _: &Ray,
+        hit_record: &HitRecord,
+        _: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        Some(ScatterRecord::Diffuse {
+            attenuation: self
+                .albedo
                 .value(hit_record.u, hit_record.v, hit_record.point),
-            Ray::new(hit_record.point, direction, ray.time),
-        ))
+            pdf: Box::new(CosinePdf::new(hit_record.normal)),
+        })
     }
 
     fn emitted(&self, _: f64, _: f64, _: Vec3) -> Color {
         Color::zero()
     }
+
+    fn scattering_pdf(&self, _: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = Vec3::dot(hit_record.normal, Vec3::unit_vector(scattered.direction));
+        (cosine / PI).max(0.0)
+    }
+
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .value(hit_record.u, hit_record.v, hit_record.point)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 impl<T: Texture + Copy> Clone for Lambertian<T> {
     fn clone(&self) -> Self {
         Self {
             albedo: self.albedo,
+            id: self.id,
         }
     }
 }
@@ -54,37 +148,70 @@ impl<T: Texture + Copy> Clone for Lambertian<T> {
 pub struct Metal {
     pub albedo: Color,
     pub fuzz: f64,
+    pub id: u32,
 }
 
 impl Metal {
     pub fn new(color: Color, fuzz: f64) -> Self {
         Self {
-            albedo: color,
+            albedo: color::to_working(color),
             fuzz,
+            id: next_id(),
         }
     }
 }
 
+impl Metal {
+    // 粗糙度换算成 Phong 波瓣的指数 波瓣的宽度与原先在反射方向上加随机偏移时相近
+    fn exponent(&self) -> f64 {
+        5.0 / (self.fuzz * self.fuzz)
+    }
+}
+
+// 光滑的金属是镜面反射 粗糙的金属按反射方向周围的波瓣采样 可以朝光源采样并参与多重重要性采样
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        _: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let reflected = Vec3::reflect(Vec3::unit_vector(ray.direction), hit_record.normal);
-        if Vec3::dot(reflected, hit_record.normal) > 0.0 {
-            Some((
-                self.albedo,
-                Ray::new(
-                    hit_record.point,
-                    reflected + Vec3::random_in_unit_sphere() * self.fuzz,
-                    ray.time,
-                ),
-            ))
-        } else {
-            None
+        if Vec3::dot(reflected, hit_record.normal) <= 0.0 {
+            return None;
+        }
+        if self.fuzz <= 0.0 {
+            return Some(ScatterRecord::Specular {
+                attenuation: self.albedo,
+                ray: Ray::new(hit_record.point, reflected, ray.time),
+            });
+        }
+        Some(ScatterRecord::Diffuse {
+            attenuation: self.albedo,
+            pdf: Box::new(PhongPdf::new(reflected, self.exponent())),
+        })
+    }
+
+    // 落到表面以下的方向被吸收
+    fn scattering_pdf(&self, ray: &Ray, hit_record: &HitRecord, scattered: &Ray) -> f64 {
+        if self.fuzz <= 0.0 || Vec3::dot(scattered.direction, hit_record.normal) <= 0.0 {
+            return 0.0;
         }
+        let reflected = Vec3::reflect(Vec3::unit_vector(ray.direction), hit_record.normal);
+        PhongPdf::new(reflected, self.exponent()).value(scattered.direction)
     }
 
     fn emitted(&self, _: f64, _: f64, _: Vec3) -> Color {
         Color::zero()
     }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        self.albedo
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 // 折射
@@ -92,11 +219,16 @@ impl Material for Metal {
 pub struct Dielectric {
     pub ir: f64,
     pub fuzz: f64,
+    pub id: u32,
 }
 
 impl Dielectric {
     pub fn new(ir: f64, fuzz: f64) -> Self {
-        Self { ir, fuzz }
+        Self {
+            ir,
+            fuzz,
+            id: next_id(),
+        }
     }
 
     // 非全反射时 折射存在概率
@@ -109,7 +241,12 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let refraction_ratio = if hit_record.front_face {
             1.0 / self.ir
         } else {
@@ -119,86 +256,125 @@ impl Material for Dielectric {
         let cos_theta = Vec3::dot(-unit_direction, hit_record.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_reflact = refraction_ratio * sin_theta > 1.0;
-        let mut rng = rand::thread_rng();
-        let rand_double: f64 = rng.gen();
+        let rand_double: f64 = sampler.gen();
         if cannot_reflact || Dielectric::reflectance(cos_theta, refraction_ratio) > rand_double {
             // 反射
             let reflected = Vec3::reflect(unit_direction, hit_record.normal);
-            Some((
-                Color::new(1.0, 1.0, 1.0),
-                Ray::new(hit_record.point, reflected, ray.time),
-            ))
+            Some(ScatterRecord::Specular {
+                attenuation: Color::new(1.0, 1.0, 1.0),
+                ray: Ray::new(hit_record.point, reflected, ray.time),
+            })
         } else {
             // 折射
             let refracted = Vec3::refract(unit_direction, hit_record.normal, refraction_ratio);
-            Some((
-                Color::new(1.0, 1.0, 1.0),
-                Ray::new(
+            Some(ScatterRecord::Specular {
+                attenuation: Color::new(1.0, 1.0, 1.0),
+                ray: Ray::new(
                     hit_record.point,
-                    refracted + Vec3::random_in_unit_sphere() * self.fuzz,
+                    refracted + Vec3::random_in_unit_sphere(sampler) * self.fuzz,
                     ray.time,
                 ),
-            ))
+            })
         }
     }
 
     fn emitted(&self, _: f64, _: f64, _: Vec3) -> Color {
         Color::zero()
     }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 // 光源
 #[derive(Copy, Clone)]
 pub struct DiffuseLight<T: Texture> {
     pub emit: T,
+    pub id: u32,
 }
 
 impl<T: Texture> DiffuseLight<T> {
     pub fn new(emit: T) -> Self {
-        Self { emit }
+        Self {
+            emit,
+            id: next_id(),
+        }
     }
 }
 
 impl<T: Texture> Material for DiffuseLight<T> {
-    fn scatter(&self, _: &Ray, _: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(&self, _: &Ray, _: &HitRecord, _: &mut dyn Sampler) -> Option<ScatterRecord> {
         None
     }
 
     fn emitted(&self, u: f64, v: f64, p: Vec3) -> Color {
         self.emit.value(u, v, p)
     }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 // 各向同性散射
 #[derive(Copy)]
 pub struct Isotropic<T: Texture> {
     pub albedo: T,
+    pub id: u32,
 }
 
 impl<T: Texture> Isotropic<T> {
     pub fn new(albedo: T) -> Self {
-        Self { albedo }
+        Self {
+            albedo,
+            id: next_id(),
+        }
     }
 }
 
 impl<T: Texture> Material for Isotropic<T> {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
-        Some((
-            self.albedo
+    fn scatter(
+        &self,
+        _: &Ray,
+        hit_record: &HitRecord,
+        _: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
+        Some(ScatterRecord::Diffuse {
+            attenuation: self
+                .albedo
                 .value(hit_record.u, hit_record.v, hit_record.point),
-            Ray::new(hit_record.point, Vec3::random_in_unit_sphere(), ray.time),
-        ))
+            pdf: Box::new(SpherePdf),
+        })
     }
 
     fn emitted(&self, _: f64, _: f64, _: Vec3) -> Color {
         Color::zero()
     }
+
+    fn scattering_pdf(&self, _: &Ray, _: &HitRecord, _: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn albedo(&self, hit_record: &HitRecord) -> Color {
+        self.albedo
+            .value(hit_record.u, hit_record.v, hit_record.point)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 impl<T: Texture + Copy> Clone for Isotropic<T> {
     fn clone(&self) -> Self {
         Self {
             albedo: self.albedo,
+            id: self.id,
         }
     }
 }
@@ -208,11 +384,17 @@ pub struct ColoredDielectric {
     pub ir: f64,
     pub fuzz: f64,
     pub color: Color,
+    pub id: u32,
 }
 
 impl ColoredDielectric {
     pub fn new(ir: f64, fuzz: f64, color: Color) -> Self {
-        Self { ir, fuzz, color }
+        Self {
+            ir,
+            fuzz,
+            color: color::to_working(color),
+            id: next_id(),
+        }
     }
 
     // 非全反射时 折射存在概率
@@ -225,7 +407,12 @@ impl ColoredDielectric {
 }
 
 impl Material for ColoredDielectric {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Color, Ray)> {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterRecord> {
         let refraction_ratio = if hit_record.front_face {
             1.0 / self.ir
         } else {
@@ -235,27 +422,37 @@ impl Material for ColoredDielectric {
         let cos_theta = Vec3::dot(-unit_direction, hit_record.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         let cannot_reflact = refraction_ratio * sin_theta > 1.0;
-        let mut rng = rand::thread_rng();
-        let rand_double: f64 = rng.gen();
+        let rand_double: f64 = sampler.gen();
         if cannot_reflact || Self::reflectance(cos_theta, refraction_ratio) > rand_double {
             // 反射
             let reflected = Vec3::reflect(unit_direction, hit_record.normal);
-            Some((self.color, Ray::new(hit_record.point, reflected, ray.time)))
+            Some(ScatterRecord::Specular {
+                attenuation: self.color,
+                ray: Ray::new(hit_record.point, reflected, ray.time),
+            })
         } else {
             // 折射
             let refracted = Vec3::refract(unit_direction, hit_record.normal, refraction_ratio);
-            Some((
-                self.color,
-                Ray::new(
+            Some(ScatterRecord::Specular {
+                attenuation: self.color,
+                ray: Ray::new(
                     hit_record.point,
-                    refracted + Vec3::random_in_unit_sphere() * self.fuzz,
+                    refracted + Vec3::random_in_unit_sphere(sampler) * self.fuzz,
                     ray.time,
                 ),
-            ))
+            })
         }
     }
 
     fn emitted(&self, _: f64, _: f64, _: Vec3) -> Color {
         Color::zero()
     }
+
+    fn albedo(&self, _: &HitRecord) -> Color {
+        self.color
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
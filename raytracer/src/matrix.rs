@@ -0,0 +1,289 @@
+// 4x4 矩阵
+// 按行存放 作用于列向量 点的第四个分量为 1 向量为 0
+// 链式构造时后调用的变换后施加 如 Matrix4::identity().rotate(axis, 30.0).translate(offset) 先旋转再平移
+
+use crate::vec3::Vec3;
+use std::ops::Mul;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let mut result = Self::identity();
+        for i in 0..3 {
+            result.m[i][3] = offset[i];
+        }
+        result
+    }
+
+    pub fn scaling(scale: Vec3) -> Self {
+        let mut result = Self::identity();
+        for i in 0..3 {
+            result.m[i][i] = scale[i];
+        }
+        result
+    }
+
+    // 绕过原点的任意轴旋转 角度为度 右手系 绕 y 轴时与 RotateY 相同
+    pub fn rotation(axis: Vec3, degrees: f64) -> Self {
+        let a = Vec3::unit_vector(axis);
+        let (s, c) = degrees.to_radians().sin_cos();
+        let t = 1.0 - c;
+        Self {
+            m: [
+                [
+                    t * a.x * a.x + c,
+                    t * a.x * a.y - s * a.z,
+                    t * a.x * a.z + s * a.y,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.y + s * a.z,
+                    t * a.y * a.y + c,
+                    t * a.y * a.z - s * a.x,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.z - s * a.y,
+                    t * a.y * a.z + s * a.x,
+                    t * a.z * a.z + c,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // 把物体放到 from 处 并让它的 +z 轴指向 to
+    // up 不能与视线平行
+    pub fn looking_at(from: Vec3, to: Vec3, up: Vec3) -> Self {
+        let z = Vec3::unit_vector(to - from);
+        let x = Vec3::unit_vector(Vec3::cross(up, z));
+        let y = Vec3::cross(z, x);
+        let mut result = Self::identity();
+        for (j, v) in [x, y, z, from].iter().enumerate() {
+            for i in 0..3 {
+                result.m[i][j] = v[i];
+            }
+        }
+        result
+    }
+
+    // 在已有的变换之后再施加 other
+    pub fn then(self, other: Matrix4) -> Self {
+        other * self
+    }
+
+    pub fn translate(self, offset: Vec3) -> Self {
+        self.then(Self::translation(offset))
+    }
+
+    pub fn rotate(self, axis: Vec3, degrees: f64) -> Self {
+        self.then(Self::rotation(axis, degrees))
+    }
+
+    pub fn scale(self, scale: f64) -> Self {
+        self.then(Self::scaling(Vec3::new(scale, scale, scale)))
+    }
+
+    pub fn scale_xyz(self, scale: Vec3) -> Self {
+        self.then(Self::scaling(scale))
+    }
+
+    pub fn look_at(self, from: Vec3, to: Vec3, up: Vec3) -> Self {
+        self.then(Self::looking_at(from, to, up))
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+
+    // 高斯-约当消元 矩阵奇异或含有 NaN 与无穷大时返回 None
+    pub fn inverse(&self) -> Option<Self> {
+        if !self.m.iter().flatten().all(|v| v.is_finite()) {
+            return None;
+        }
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col + 1..4).fold(col, |best, i| {
+                if a[i][col].abs() > a[best][col].abs() {
+                    i
+                } else {
+                    best
+                }
+            });
+            // 消元中溢出得到的 NaN 也当作奇异
+            let p = a[pivot][col].abs();
+            if p.is_nan() || p < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= d;
+                inv[col][j] /= d;
+            }
+            for i in 0..4 {
+                if i != col {
+                    let f = a[i][col];
+                    for j in 0..4 {
+                        a[i][j] -= f * a[col][j];
+                        inv[i][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+
+    // 左上 3x3 部分的行列式 即体积的缩放倍数
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        self.transform_vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Matrix4, b: Matrix4) {
+        for (row_a, row_b) in a.m.iter().zip(&b.m) {
+            for (x, y) in row_a.iter().zip(row_b) {
+                assert!((x - y).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    fn assert_near_vec(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let matrix = Matrix4::identity()
+            .scale_xyz(Vec3::new(1.0, 2.0, 0.5))
+            .rotate(Vec3::new(1.0, 1.0, 0.0), 37.0)
+            .then(Matrix4 {
+                m: [
+                    [1.0, 0.5, 0.0, 0.0],
+                    [0.0, 1.0, 0.0, 0.0],
+                    [0.0, 0.0, 1.0, 0.0],
+                    [0.0, 0.0, 0.0, 1.0],
+                ],
+            })
+            .translate(Vec3::new(3.0, -4.0, 5.0));
+        let inverse = matrix.inverse().unwrap();
+        assert_near(matrix * inverse, Matrix4::identity());
+        assert_near(inverse * matrix, Matrix4::identity());
+    }
+
+    #[test]
+    fn inverse_rejects_singular_and_nan() {
+        let flat = Matrix4::scaling(Vec3::new(1.0, 0.0, 1.0));
+        assert!(flat.inverse().is_none());
+        let mut nan = Matrix4::identity();
+        nan.m[0][0] = f64::NAN;
+        assert!(nan.inverse().is_none());
+        let mut nan = Matrix4::identity();
+        nan.m[1][3] = f64::NAN;
+        assert!(nan.inverse().is_none());
+        let mut infinite = Matrix4::identity();
+        infinite.m[2][1] = f64::INFINITY;
+        assert!(infinite.inverse().is_none());
+    }
+
+    #[test]
+    fn rotation_about_y_matches_rotate_y() {
+        // RotateY 原先的公式 x' = cos x + sin z  z' = -sin x + cos z
+        let theta: f64 = 15.0;
+        let (s, c) = theta.to_radians().sin_cos();
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        let expected = Vec3::new(c * p.x + s * p.z, p.y, -s * p.x + c * p.z);
+        // 轴不必是单位向量
+        for axis in [Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 3.0, 0.0)] {
+            let rotation = Matrix4::rotation(axis, theta);
+            assert_near_vec(rotation.transform_point(p), expected);
+        }
+    }
+
+    #[test]
+    fn rotation_about_diagonal_cycles_the_axes() {
+        let rotation = Matrix4::rotation(Vec3::new(1.0, 1.0, 1.0), 120.0);
+        assert_near_vec(
+            rotation.transform_vector(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert_near_vec(
+            rotation.transform_vector(Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn looking_at_points_z_at_the_target() {
+        let from = Vec3::new(1.0, 2.0, 3.0);
+        let to = Vec3::new(4.0, 2.0, 7.0);
+        let matrix = Matrix4::looking_at(from, to, Vec3::new(0.0, 1.0, 0.0));
+        assert_near_vec(matrix.transform_point(Vec3::zero()), from);
+        assert_near_vec(matrix.transform_point(Vec3::new(0.0, 0.0, 5.0)), to);
+        assert_near_vec(
+            matrix.transform_vector(Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+    }
+}
@@ -1,17 +1,22 @@
 //
 
+use crate::aabb::Aabb;
 use crate::bvh_node::BVHNode;
 use crate::hittable::aarect::*;
 use crate::hittable::constant_medium::ConstantMedium;
-use crate::hittable::instance::{RotateY, Translate};
+use crate::hittable::instance::{blas, Instance, Transform};
 use crate::hittable::sphere::Sphere;
+use crate::hittable::Hittable;
 use crate::hittable_list::HittableList;
 use crate::material::*;
+use crate::matrix::Matrix4;
 use crate::obj_file::load;
 use crate::rectbox::RectBox;
 use crate::texture::*;
 use crate::vec3::{Color, Vec3};
+use rand::rngs::StdRng;
 use rand::Rng;
+use std::sync::Arc;
 use std::vec::Vec;
 
 #[derive(Copy, Clone)]
@@ -32,9 +37,8 @@ impl Block {
         }
     }
 
-    pub fn create(boxes_per_side: usize, offset: f64) -> Vec<Vec<Block>> {
-        let mut rng = rand::thread_rng();
-        let noise = NoiseTexture::new(0.03);
+    pub fn create(boxes_per_side: usize, offset: f64, rng: &mut StdRng) -> Vec<Vec<Block>> {
+        let noise = NoiseTexture::new(0.03, rng);
         let mut map: Vec<Vec<Block>> = vec![vec![Block::new(); boxes_per_side]; boxes_per_side];
         for (i, it) in map.iter_mut().enumerate().take(boxes_per_side) {
             for (j, block) in it.iter_mut().enumerate().take(boxes_per_side) {
@@ -165,10 +169,10 @@ impl Block {
         )));
     }
 
-    pub fn flower(x: f64, y: f64, z: f64, list: &mut HittableList) {
-        let c = (Color::random() + Color::one() * 0.5) * 0.66;
+    // 每朵花的形状与颜色都随机 只有三个矩形 直接放进场景不必共享
+    pub fn flower(x: f64, y: f64, z: f64, list: &mut HittableList, rng: &mut StdRng) {
+        let c = (Color::random(rng) + Color::one() * 0.5) * 0.66;
         let mat = Lambertian::new(SolidColor::new(c));
-        let mut rng = rand::thread_rng();
         let offset = rng.gen::<f64>() * 0.7;
         list.add(Box::new(XZRect::new(
             x + offset,
@@ -196,59 +200,91 @@ impl Block {
         )));
     }
 
-    pub fn coral(x: f64, y: f64, z: f64, list: &mut HittableList) {
-        let c = (Color::random() + Color::one() * 0.5) * 1.0;
+    // 位于原点的一棵树 只建一次 各处的树都是它的实例
+    fn tree_blas() -> Arc<dyn Hittable> {
+        let mut list = HittableList::new();
+        Self::tree(0.0, 0.0, 0.0, &mut list);
+        blas(list)
+    }
+
+    // 珊瑚会发光 不共享
+    // 光源列表中每丛珊瑚只放一个包围球 光源采样的代价与矩形数无关
+    // 朝包围球采样的方向照常求交 没打到矩形时贡献为零 结果仍然无偏
+    pub fn coral(
+        x: f64,
+        y: f64,
+        z: f64,
+        list: &mut HittableList,
+        lights: &mut HittableList,
+        rng: &mut StdRng,
+    ) {
+        let c = (Color::random(rng) + Color::one() * 0.5) * 1.0;
         let mat = DiffuseLight::new(SolidColor::new(c));
-        let mut rng = rand::thread_rng();
+        let mut bbox = Aabb::new(
+            Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        );
+        let mut add = |rect: XZRect<_>| {
+            if let Some(rect_box) = rect.bounding_box(0.0, 1.0) {
+                bbox = Aabb::surrounding_box(&bbox, &rect_box);
+            }
+            list.add(Box::new(rect));
+        };
         let offset = rng.gen::<f64>() * 0.8;
-        list.add(Box::new(XZRect::new(
+        add(XZRect::new(
             x + offset,
             x + offset + 0.6,
             z + offset,
             z + offset + 0.6,
             y + rng.gen::<f64>() / 4.0,
             mat,
-        )));
-        list.add(Box::new(XZRect::new(
+        ));
+        add(XZRect::new(
             x + 1.0 - offset - 0.55,
             x + 1.0 - offset,
             z + offset - 0.25,
             z + offset + 0.3,
             y + rng.gen::<f64>() / 5.0 + 0.05,
             mat,
-        )));
-        list.add(Box::new(XZRect::new(
+        ));
+        add(XZRect::new(
             x + 1.0 - offset,
             x + 1.0 - offset + 0.6,
             z + 1.0 - offset - 0.6,
             z + 1.0 - offset,
             y + rng.gen::<f64>() / 3.0,
             mat,
-        )));
+        ));
         let offset = rng.gen::<f64>() * 0.5;
-        list.add(Box::new(XZRect::new(
+        add(XZRect::new(
             x + offset,
             x + offset + 0.3,
             z + offset,
             z + offset + 0.3,
             y + rng.gen::<f64>() / 4.0 + 0.5,
             mat,
-        )));
-        list.add(Box::new(XZRect::new(
+        ));
+        add(XZRect::new(
             x + 1.0 - offset - 0.35,
             x + 1.0 - offset,
             z + offset + 0.25,
             z + offset + 0.6,
             y + rng.gen::<f64>() / 5.0 + 0.55,
             mat,
-        )));
-        list.add(Box::new(XZRect::new(
+        ));
+        add(XZRect::new(
             x + 1.0 - offset,
             x + 1.0 - offset + 0.3,
             z + 1.0 - offset - 0.3,
             z + 1.0 - offset,
             y + rng.gen::<f64>() / 3.0 + 0.5,
             mat,
+        ));
+        let center = (bbox.min + bbox.max) / 2.0;
+        lights.add(Box::new(Sphere::new(
+            center,
+            (bbox.max - center).length(),
+            mat,
         )));
         list.add(Box::new(RectBox::new(
             Vec3::new(x + 0.2, y + 0.1, z + 0.2),
@@ -257,8 +293,9 @@ impl Block {
         )));
     }
 
-    // 像素世界生成
-    pub fn the_world() -> HittableList {
+    // 像素世界生成 返回的物体都已编号
+    // 每个方块 每棵树 每朵花和珊瑚各有自己的编号
+    pub fn the_world(lights: &mut HittableList, rng: &mut StdRng) -> HittableList {
         let mut boxes1 = HittableList::new();
         let mud = Lambertian::new(SolidColor::new(Color::new(0.36, 0.25, 0.16)));
         let grass = Lambertian::new(SolidColor::new(Color::new(0.50, 0.72, 0.36)));
@@ -269,7 +306,8 @@ impl Block {
         // let coral = Lambertian::new(SolidColor::new(Color::new(0.0, 0.0, 1.0)));
 
         let boxes_per_side = 200;
-        let map = Block::create(boxes_per_side, -6.0);
+        let map = Block::create(boxes_per_side, -6.0, rng);
+        let tree = Self::tree_blas();
         // Self::flower(8.0, 14.0, 8.0, &mut boxes1);
         // boxes1.add(Box::new(RectBox::new(
         //     Vec3::new(8.0, 13.0, 8.0),
@@ -277,6 +315,7 @@ impl Block {
         //     mud,
         // )));
 
+        let mut next_id = 1;
         for (i, it) in map.into_iter().enumerate() {
             for (j, block) in it.into_iter().enumerate() {
                 if (0.33..3.3).contains(&(j as f32 / i as f32)) && (j as i32 + i as i32) > 40 {
@@ -293,6 +332,7 @@ impl Block {
                         2 => sand,
                         _ => sand,
                     };
+                    let mut start = boxes1.objects.len();
                     boxes1.add(Box::new(XZRect::new(x0, x1, z0, z1, y1, block_mat)));
                     boxes1.add(Box::new(YZRect::new(y0 - 0.1, y1, z0, z1, x0, block_mat)));
                     boxes1.add(Box::new(XYRect::new(x0, x1, y0 - 0.1, y1, z0, block_mat)));
@@ -325,12 +365,23 @@ impl Block {
                                     grass,
                                 )));
                             }
+                            // 方块上的树和花单独编号
+                            boxes1.tag_since(start, next_id);
+                            next_id += 1;
+                            start = boxes1.objects.len();
                             match block.decoration {
                                 1 => {
-                                    Self::tree(x0, y1, z0, &mut boxes1);
+                                    let offset = Vec3::new(x0, y1, z0);
+                                    let tree = Instance::new(
+                                        tree.clone(),
+                                        Matrix4::translation(offset),
+                                        None,
+                                    )
+                                    .expect("translation is invertible");
+                                    boxes1.add(Box::new(tree));
                                 }
                                 2 => {
-                                    Self::flower(x0, y1, z0, &mut boxes1);
+                                    Self::flower(x0, y1, z0, &mut boxes1, rng);
                                 }
                                 _ => (),
                             }
@@ -338,10 +389,17 @@ impl Block {
                         2 => {}
                         _ => {
                             if block.decoration == 3 {
-                                Self::coral(x0, y1, z0, &mut boxes1);
+                                boxes1.tag_since(start, next_id);
+                                next_id += 1;
+                                start = boxes1.objects.len();
+                                Self::coral(x0, y1, z0, &mut boxes1, lights, rng);
                             }
                         }
                     }
+                    if boxes1.objects.len() > start {
+                        boxes1.tag_since(start, next_id);
+                        next_id += 1;
+                    }
                 }
             }
         }
@@ -352,16 +410,24 @@ impl Block {
         // let log = Lambertian::new(SolidColor::new(Color::new(0.3, 0.23, 0.14)));
         let log = DiffuseLight::new(SolidColor::new(Color::new(3.0, 1.5, 1.0)));
         let obj = load("raytracer/src/obj/cottage_obj.obj".to_string(), log, 0.4);
-        objects.add(Box::new(Translate::new(
-            Box::new(RotateY::new(Box::new(HittableList { objects: obj }), 180.0)),
-            Vec3::new(32.0, 1.0, 56.0),
-        )));
+        objects.add(Box::new(
+            Transform::new(
+                Box::new(HittableList { objects: obj }),
+                Matrix4::rotation(Vec3::new(0.0, 1.0, 0.0), 180.0)
+                    .translate(Vec3::new(32.0, 1.0, 56.0)),
+            )
+            .expect("rigid motion is invertible"),
+        ));
 
-        objects.add(Box::new(RectBox::new(
-            Vec3::new(28.0, 0.0, 42.0),
-            Vec3::new(29.0, 0.6, 43.0),
-            DiffuseLight::new(SolidColor::new(Color::new(10.0, 8.0, 5.0))),
-        )));
+        let lamp = || {
+            RectBox::new(
+                Vec3::new(28.0, 0.0, 42.0),
+                Vec3::new(29.0, 0.6, 43.0),
+                DiffuseLight::new(SolidColor::new(Color::new(10.0, 8.0, 5.0))),
+            )
+        };
+        objects.add(Box::new(lamp()));
+        lights.add(Box::new(lamp()));
         objects.add(Box::new(RectBox::new(
             Vec3::new(27.8, 0.0, 41.8),
             Vec3::new(29.2, 0.25, 43.2),
@@ -399,12 +465,44 @@ impl Block {
         //     Isotropic::new(SolidColor::new(Color::one())),
         // )));
 
-        objects.add(Box::new(Sphere::new(
+        let sun = Sphere::new(
             Vec3::new(-10.0, -30.0, 20.0),
             5.0,
             DiffuseLight::new(SolidColor::new(Color::new(10.0, 10.0, 10.0))),
-        )));
+        );
+        objects.add(Box::new(sun));
+        lights.add(Box::new(sun));
+
+        objects.tagged_from(next_id)
+    }
+}
+
+// 一万棵共享同一几何体的树 内存与一棵树相差无几
+pub fn forest(rng: &mut StdRng) -> HittableList {
+    let mut objects = HittableList::new();
+    let grass = Lambertian::new(SolidColor::new(Color::new(0.50, 0.72, 0.36)));
+    let side = 100;
+    let spacing = 6.0;
+    let size = side as f64 * spacing;
+    objects.add(Box::new(XZRect::new(0.0, size, 0.0, size, 0.0, grass)));
+    let tree = Block::tree_blas();
+    for i in 0..side {
+        for j in 0..side {
+            let x = (i as f64 + rng.gen_range(0.25..0.75)) * spacing;
+            let z = (j as f64 + rng.gen_range(0.25..0.75)) * spacing;
+            let matrix = Matrix4::translation(Vec3::new(-0.5, 0.0, -0.5))
+                .scale(rng.gen_range(0.8..1.2))
+                .rotate(Vec3::new(0.0, 1.0, 0.0), rng.gen_range(0.0..360.0))
+                .translate(Vec3::new(x, 0.0, z));
+            let tree = Instance::new(tree.clone(), matrix, None).expect("scale is at least 0.8");
+            objects.add(Box::new(tree));
+        }
+    }
+    objects
+}
 
-        objects
+impl Default for Block {
+    fn default() -> Self {
+        Self::new()
     }
 }
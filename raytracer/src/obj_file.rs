@@ -9,12 +9,21 @@ use crate::vec3::Vec3;
 use std::string::String;
 use tobj::{load_obj, LoadOptions};
 
-pub fn load<M: 'static + Material + Clone + Copy>(
+pub fn load<M: 'static + Material + Clone>(
     file_name: String,
     material: M,
     scale: f64,
 ) -> Vec<Box<dyn Hittable>> {
-    let obj = load_obj(
+    try_load(file_name, material, scale).expect("Failed to load OBJ file")
+}
+
+// 文件无法读取或解析时返回错误
+pub fn try_load<M: 'static + Material + Clone>(
+    file_name: String,
+    material: M,
+    scale: f64,
+) -> Result<Vec<Box<dyn Hittable>>, tobj::LoadError> {
+    let (models, _) = load_obj(
         file_name,
         &LoadOptions {
             single_index: false,
@@ -22,10 +31,7 @@ pub fn load<M: 'static + Material + Clone + Copy>(
             ignore_points: true,
             ignore_lines: true,
         },
-    );
-    assert!(obj.is_ok());
-
-    let (models, _) = obj.expect("Failed to load OBJ file");
+    )?;
 
     let mut objects = vec![];
 
@@ -51,7 +57,7 @@ pub fn load<M: 'static + Material + Clone + Copy>(
                 points[indices[f] as usize],
                 points[indices[f + 1] as usize],
                 points[indices[f + 2] as usize],
-                material,
+                material.clone(),
             )))
         }
 
@@ -72,5 +78,5 @@ pub fn load<M: 'static + Material + Clone + Copy>(
         // }
     }
 
-    objects
+    Ok(objects)
 }
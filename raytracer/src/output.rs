@@ -0,0 +1,638 @@
+// 图像输出
+// 按扩展名选择格式 .exr .hdr .pfm 保存线性数据 其余格式保存映射后的 8 位图像
+// 也能读回自己写出的 .exr 与 .pfm 以便重新做色调映射
+
+use crate::film::Film;
+use crate::tonemap::ToneMapping;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum OutputError {
+    Io(io::Error),
+    Image(image::ImageError),
+    UnknownFormat(String),
+    // 文件内容不符合格式 或用到了不支持的特性
+    Invalid(String),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputError::Io(err) => write!(f, "{}", err),
+            OutputError::Image(err) => write!(f, "{}", err),
+            OutputError::UnknownFormat(ext) => {
+                write!(
+                    f,
+                    "unknown linear image format \"{}\", use .exr or .pfm",
+                    ext
+                )
+            }
+            OutputError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<io::Error> for OutputError {
+    fn from(err: io::Error) -> Self {
+        OutputError::Io(err)
+    }
+}
+
+pub fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+// 保存线性数据的格式
+pub fn is_linear(path: &Path) -> bool {
+    matches!(extension(path).as_str(), "exr" | "hdr" | "pfm")
+}
+
+// 同一目录下在扩展名前加上 tag 的路径 如 image.preview.png
+pub fn sibling(path: &Path, tag: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}.{}.{}", stem, tag, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{}.{}", stem, tag)),
+    }
+}
+
+// 色调映射只作用于 8 位格式
+pub fn write(film: &Film, path: &Path, tone: &ToneMapping) -> Result<(), OutputError> {
+    match extension(path).as_str() {
+        "exr" => write_exr(
+            path,
+            film.width,
+            film.height,
+            &[
+                ("R", &film.channel(0)),
+                ("G", &film.channel(1)),
+                ("B", &film.channel(2)),
+            ],
+            &film.metadata,
+        ),
+        "hdr" => write_hdr(film, path),
+        "pfm" => write_pfm(film, path),
+        "jpg" | "jpeg" => {
+            let mut file = File::create(path)?;
+            image::DynamicImage::ImageRgb8(film.to_rgb8(tone))
+                .write_to(&mut file, image::ImageOutputFormat::Jpeg(100))
+                .map_err(OutputError::Image)
+        }
+        _ => film.to_rgb8(tone).save(path).map_err(OutputError::Image),
+    }
+}
+
+// 无压缩的 OpenEXR 每行一块 通道均为 32 位浮点
+// 通道按名称排序写入 名称可以带图层前缀 如 albedo.R
+// metadata 写成字符串类型的属性
+pub fn write_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    channels: &[(&str, &[f32])],
+    metadata: &[(String, String)],
+) -> Result<(), OutputError> {
+    if width == 0 || height == 0 {
+        return Err(OutputError::Invalid("EXR image has no pixels".to_string()));
+    }
+    let mut channels = channels.to_vec();
+    channels.sort_by(|a, b| a.0.cmp(b.0));
+    let mut out = BufWriter::new(File::create(path)?);
+
+    // 文件头
+    out.write_all(&[0x76, 0x2f, 0x31, 0x01])?;
+    out.write_all(&2u32.to_le_bytes())?;
+    let mut chlist = vec![];
+    for (name, _) in &channels {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        // FLOAT 类型 pLinear 与保留字节 x y 方向的采样间隔
+        chlist.extend_from_slice(&2i32.to_le_bytes());
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let mut attributes: Vec<(&str, &str, Vec<u8>)> = vec![
+        ("channels", "chlist", chlist),
+        ("compression", "compression", vec![0]),
+        ("dataWindow", "box2i", window.clone()),
+        ("displayWindow", "box2i", window),
+        ("lineOrder", "lineOrder", vec![0]),
+        ("pixelAspectRatio", "float", 1f32.to_le_bytes().to_vec()),
+        ("screenWindowCenter", "v2f", [0u8; 8].to_vec()),
+        ("screenWindowWidth", "float", 1f32.to_le_bytes().to_vec()),
+    ];
+    for (name, value) in metadata {
+        attributes.push((name, "string", value.as_bytes().to_vec()));
+    }
+    let mut header_size = 8;
+    for (name, kind, value) in attributes.iter() {
+        out.write_all(name.as_bytes())?;
+        out.write_all(&[0])?;
+        out.write_all(kind.as_bytes())?;
+        out.write_all(&[0])?;
+        out.write_all(&(value.len() as u32).to_le_bytes())?;
+        out.write_all(value)?;
+        header_size += name.len() + kind.len() + 2 + 4 + value.len();
+    }
+    out.write_all(&[0])?;
+    header_size += 1;
+
+    // 行偏移表 每行由行号 数据长度与各通道数据组成
+    let line_size = 8 + channels.len() * width as usize * 4;
+    let table_end = header_size + height as usize * 8;
+    for y in 0..height as usize {
+        out.write_all(&((table_end + y * line_size) as u64).to_le_bytes())?;
+    }
+    for y in 0..height as usize {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&((line_size - 8) as i32).to_le_bytes())?;
+        for (_, data) in &channels {
+            for v in &data[y * width as usize..(y + 1) * width as usize] {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// Radiance RGBE 格式 每行按通道以不压缩的片段写入
+fn write_hdr(film: &Film, path: &Path) -> Result<(), OutputError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        film.height, film.width
+    )?;
+    let width = film.width as usize;
+    for row in film.pixels.chunks(width) {
+        let rgbe: Vec<[u8; 4]> = row.iter().map(|p| to_rgbe(*p)).collect();
+        if (8..0x8000).contains(&width) {
+            out.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+            for c in 0..4 {
+                let data: Vec<u8> = rgbe.iter().map(|p| p[c]).collect();
+                for chunk in data.chunks(128) {
+                    out.write_all(&[chunk.len() as u8])?;
+                    out.write_all(chunk)?;
+                }
+            }
+        } else {
+            for p in &rgbe {
+                out.write_all(p)?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// 超出范围的值写不进 RGBE NaN 记为 0 无穷大取能表示的最大值
+const RGBE_MAX: f32 = 1e38;
+
+fn to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let [r, g, b] = rgb.map(|c| if c.is_nan() { 0.0 } else { c.min(RGBE_MAX) });
+    let v = r.max(g).max(b);
+    if v < 1e-32 {
+        return [0; 4];
+    }
+    // v = m * 2^e 其中 m 在 [0.5, 1) 内
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / 2f32.powi(e) >= 1.0 {
+        e += 1;
+    }
+    let scale = 256.0 / 2f32.powi(e);
+    [
+        (r.max(0.0) * scale) as u8,
+        (g.max(0.0) * scale) as u8,
+        (b.max(0.0) * scale) as u8,
+        (e + 128) as u8,
+    ]
+}
+
+// 便携浮点图 行从图像底部开始 负的比例表示小端序
+fn write_pfm(film: &Film, path: &Path) -> Result<(), OutputError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "PF\n{} {}\n-1.0\n", film.width, film.height)?;
+    for row in film.pixels.chunks(film.width as usize).rev() {
+        for p in row {
+            for v in p {
+                out.write_all(&v.to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+pub fn read(path: &Path) -> Result<Film, OutputError> {
+    match extension(path).as_str() {
+        "exr" => read_exr(path)?.film(["R", "G", "B"]),
+        "pfm" => read_pfm(path),
+        ext => Err(OutputError::UnknownFormat(ext.to_string())),
+    }
+}
+
+pub struct ExrImage {
+    pub width: u32,
+    pub height: u32,
+    pub channels: Vec<(String, Vec<f32>)>,
+    // 字符串类型的属性
+    pub metadata: Vec<(String, String)>,
+}
+
+impl ExrImage {
+    pub fn channel(&self, name: &str) -> Option<&[f32]> {
+        self.channels
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, data)| &data[..])
+    }
+
+    // 把三个通道组成胶片 如图层 albedo.R albedo.G albedo.B
+    pub fn film(&self, names: [&str; 3]) -> Result<Film, OutputError> {
+        let mut film = Film::new(self.width, self.height);
+        film.metadata = self.metadata.clone();
+        for (c, name) in names.iter().enumerate() {
+            let data = self
+                .channel(name)
+                .ok_or_else(|| OutputError::Invalid(format!("missing channel \"{}\"", name)))?;
+            for (p, v) in film.pixels.iter_mut().zip(data) {
+                p[c] = *v;
+            }
+        }
+        Ok(film)
+    }
+}
+
+// 读取单部分 无压缩 浮点通道的扫描线 OpenEXR
+pub fn read_exr(path: &Path) -> Result<ExrImage, OutputError> {
+    let bytes = std::fs::read(path)?;
+    let invalid = |message: &str| OutputError::Invalid(message.to_string());
+    let mut reader = ByteReader {
+        bytes: &bytes,
+        pos: 0,
+    };
+    if reader.take(4)? != [0x76, 0x2f, 0x31, 0x01] {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    if reader.u32()? & !0xff != 0 {
+        return Err(invalid(
+            "only single-part scanline OpenEXR files are supported",
+        ));
+    }
+
+    let mut names = vec![];
+    let mut window = None;
+    let mut metadata = vec![];
+    loop {
+        let name = reader.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = reader.string()?;
+        let size = reader.u32()? as usize;
+        let value = reader.take(size)?;
+        match name.as_str() {
+            "channels" => {
+                let mut list = ByteReader {
+                    bytes: value,
+                    pos: 0,
+                };
+                loop {
+                    let channel = list.string()?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    if list.u32()? != 2 {
+                        return Err(invalid("only 32-bit float channels are supported"));
+                    }
+                    list.take(12)?;
+                    names.push(channel);
+                }
+            }
+            "compression" if value != [0] => {
+                return Err(invalid("compressed OpenEXR files are not supported"));
+            }
+            "dataWindow" => {
+                let mut w = ByteReader {
+                    bytes: value,
+                    pos: 0,
+                };
+                window = Some([w.u32()?, w.u32()?, w.u32()?, w.u32()?]);
+            }
+            _ if kind == "string" => {
+                metadata.push((name, String::from_utf8_lossy(value).into_owned()));
+            }
+            _ => {}
+        }
+    }
+    let [x0, y0, x1, y1] = window.ok_or_else(|| invalid("missing dataWindow"))?;
+    // 窗口的坐标是有符号数 两端都包含在内
+    let (x0, y0, x1, y1) = (x0 as i32, y0 as i32, x1 as i32, y1 as i32);
+    if x1 < x0 || y1 < y0 {
+        return Err(invalid("dataWindow is empty"));
+    }
+    let width = (x1 as i64 - x0 as i64 + 1) as usize;
+    let height = (y1 as i64 - y0 as i64 + 1) as usize;
+    // 偏移表每行 8 字节 每行数据前有行号与长度 8 字节
+    // 分配之前先确认文件足够长 损坏的文件头不会申请巨大的内存
+    let size = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(names.len()))
+        .and_then(|n| n.checked_mul(4))
+        .and_then(|n| n.checked_add(height * 16));
+    if size.map_or(true, |size| reader.bytes.len() - reader.pos < size) {
+        return Err(invalid("file is shorter than its dataWindow"));
+    }
+    let mut channels: Vec<(String, Vec<f32>)> = names
+        .into_iter()
+        .map(|n| (n, vec![0.0; width * height]))
+        .collect();
+    // 跳过偏移表 按块中记录的行号放置数据
+    reader.take(height * 8)?;
+    for _ in 0..height {
+        let y = (reader.u32()? as i32 as i64 - y0 as i64) as usize;
+        reader.u32()?;
+        if y >= height {
+            return Err(invalid("scanline outside the data window"));
+        }
+        for (_, data) in channels.iter_mut() {
+            for v in &mut data[y * width..(y + 1) * width] {
+                *v = f32::from_bits(reader.u32()?);
+            }
+        }
+    }
+    Ok(ExrImage {
+        width: width as u32,
+        height: height as u32,
+        channels,
+        metadata,
+    })
+}
+
+fn read_pfm(path: &Path) -> Result<Film, OutputError> {
+    let bytes = std::fs::read(path)?;
+    let invalid = || OutputError::Invalid("not a color PFM file".to_string());
+    // 文件头是四个以空白分隔的字段 之后紧跟一个空白字符
+    let mut fields = vec![];
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while bytes.get(pos).map_or(false, |b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        let start = pos;
+        while bytes.get(pos).map_or(false, |b| !b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(invalid());
+        }
+        fields.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+    }
+    if fields[0] != "PF" {
+        return Err(invalid());
+    }
+    let width: u32 = fields[1].parse().map_err(|_| invalid())?;
+    let height: u32 = fields[2].parse().map_err(|_| invalid())?;
+    let scale: f32 = fields[3].parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(OutputError::Invalid("PFM image has no pixels".to_string()));
+    }
+    let data = &bytes[(pos + 1).min(bytes.len())..];
+    let size = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|n| n.checked_mul(12));
+    if size.map_or(true, |size| data.len() < size) {
+        return Err(invalid());
+    }
+
+    let value = |b: &[u8]| {
+        let b = [b[0], b[1], b[2], b[3]];
+        // 负的比例表示小端序
+        if scale < 0.0 {
+            f32::from_le_bytes(b)
+        } else {
+            f32::from_be_bytes(b)
+        }
+    };
+    let mut film = Film::new(width, height);
+    for (y, row) in data
+        .chunks(width as usize * 12)
+        .take(height as usize)
+        .enumerate()
+    {
+        // 行从图像底部开始
+        let y = height as usize - 1 - y;
+        for (x, p) in row.chunks(12).enumerate() {
+            film.pixels[y * width as usize + x] = [value(&p[0..]), value(&p[4..]), value(&p[8..])];
+        }
+    }
+    Ok(film)
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], OutputError> {
+        let end = self.pos.saturating_add(n);
+        if end > self.bytes.len() {
+            return Err(OutputError::Invalid("unexpected end of file".to_string()));
+        }
+        let result = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(result)
+    }
+
+    fn u32(&mut self) -> Result<u32, OutputError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    // 以 0 结尾的字符串
+    fn string(&mut self) -> Result<String, OutputError> {
+        let mut result = vec![];
+        loop {
+            match self.take(1)?[0] {
+                0 => return Ok(String::from_utf8_lossy(&result).to_string()),
+                b => result.push(b),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::ToneMapper;
+
+    fn from_rgbe([r, g, b, e]: [u8; 4]) -> [f32; 3] {
+        if e == 0 {
+            return [0.0; 3];
+        }
+        let scale = 2f32.powi(e as i32 - 136);
+        [r, g, b].map(|c| (c as f32 + 0.5) * scale)
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("raytracer-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn rgbe_round_trip() {
+        for rgb in [[0.25, 0.5, 1.0], [3.0, 1e-3, 0.0], [1e5, 2e5, 4e5]] {
+            let back = from_rgbe(to_rgbe(rgb));
+            let max = rgb.iter().cloned().fold(0.0, f32::max);
+            for (a, b) in rgb.iter().zip(back.iter()) {
+                assert!((a - b).abs() <= max / 128.0, "{:?} -> {:?}", rgb, back);
+            }
+        }
+        assert_eq!(to_rgbe([0.0; 3]), [0; 4]);
+    }
+
+    #[test]
+    fn rgbe_clamps_non_finite() {
+        assert_eq!(to_rgbe([f32::NAN; 3]), [0; 4]);
+        let [_, _, _, e] = to_rgbe([f32::INFINITY, 1.0, f32::NAN]);
+        assert_eq!(e, 255);
+        assert_eq!(
+            to_rgbe([f32::INFINITY, 0.0, 0.0]),
+            to_rgbe([RGBE_MAX, 0.0, 0.0])
+        );
+        assert_eq!(
+            to_rgbe([-f32::INFINITY, 1.0, 1.0]),
+            to_rgbe([0.0, 1.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let mut film = Film::new(3, 2);
+        for (k, p) in film.pixels.iter_mut().enumerate() {
+            *p = [k as f32, -0.5 * k as f32, 1e6 / (k + 1) as f32];
+        }
+        film.metadata = vec![("spp".to_string(), "16".to_string())];
+        let path = temp_path("round_trip.exr");
+        write(
+            &film,
+            &path,
+            &ToneMapping::new(0.0, None, ToneMapper::Clamp),
+        )
+        .unwrap();
+        let image = read_exr(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.metadata, film.metadata);
+        assert_eq!(image.film(["R", "G", "B"]).unwrap().pixels, film.pixels);
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let mut film = Film::new(2, 3);
+        for (k, p) in film.pixels.iter_mut().enumerate() {
+            *p = [k as f32, 0.25, -(k as f32)];
+        }
+        let path = temp_path("round_trip.pfm");
+        write(
+            &film,
+            &path,
+            &ToneMapping::new(0.0, None, ToneMapper::Clamp),
+        )
+        .unwrap();
+        let back = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((back.width, back.height), (2, 3));
+        assert_eq!(back.pixels, film.pixels);
+    }
+
+    // 正的比例表示大端序
+    #[test]
+    fn pfm_big_endian() {
+        let mut bytes = b"PF\n1 1\n1.0\n".to_vec();
+        for v in [1.0f32, 2.0, 3.0] {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        }
+        let path = temp_path("big_endian.pfm");
+        std::fs::write(&path, bytes).unwrap();
+        let film = read_pfm(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(film.pixels, vec![[1.0, 2.0, 3.0]]);
+    }
+
+    #[test]
+    fn pfm_rejects_bad_headers() {
+        for (name, bytes) in [
+            ("zero.pfm", &b"PF\n0 4\n-1.0\n"[..]),
+            ("short.pfm", &b"PF\n2 2\n-1.0\n\0\0\0\0"[..]),
+            ("gray.pfm", &b"Pf\n1 1\n-1.0\n\0\0\0\0"[..]),
+            ("huge.pfm", &b"PF\n4294967295 4294967295\n-1.0\n"[..]),
+        ] {
+            let path = temp_path(name);
+            std::fs::write(&path, bytes).unwrap();
+            let result = read_pfm(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert!(matches!(result, Err(OutputError::Invalid(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn exr_rejects_other_files() {
+        let path = temp_path("not.exr");
+        std::fs::write(&path, b"PF\n1 1\n-1.0\n").unwrap();
+        let result = read_exr(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(OutputError::Invalid(_))));
+    }
+
+    #[test]
+    fn exr_rejects_bad_data_windows() {
+        let path = temp_path("window.exr");
+        let film = Film::new(2, 2);
+        write(
+            &film,
+            &path,
+            &ToneMapping::new(0.0, None, ToneMapper::Clamp),
+        )
+        .unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        let key = b"dataWindow\0box2i\0";
+        let at = bytes.windows(key.len()).position(|w| w == key).unwrap() + key.len() + 4;
+        // 溢出的宽高 过大的高度 倒置的窗口
+        for window in [
+            [0u32, 0, 0xffff_fffe, 0xffff_fffe],
+            [0, 0, 1, 0x7fff_ffff],
+            [0, 0, 1, 0x8000_0000],
+            [5, 0, 1, 1],
+        ] {
+            let mut patched = bytes.clone();
+            for (k, v) in window.iter().enumerate() {
+                patched[at + 4 * k..at + 4 * k + 4].copy_from_slice(&v.to_le_bytes());
+            }
+            std::fs::write(&path, &patched).unwrap();
+            assert!(matches!(read_exr(&path), Err(OutputError::Invalid(_))));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn exr_refuses_empty_images() {
+        let path = temp_path("empty.exr");
+        let result = write_exr(&path, 0, 4, &[("R", &[])], &[]);
+        assert!(matches!(result, Err(OutputError::Invalid(_))));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,154 @@
+// 概率密度函数
+// 按一定分布生成方向 并给出方向对应的概率密度
+
+use crate::hittable::Hittable;
+use crate::onb::Onb;
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::f64::consts::PI;
+
+pub trait Pdf {
+    fn value(&self, direction: Vec3) -> f64;
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3;
+}
+
+// 余弦分布 用于漫反射
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: Vec3) -> Self {
+        Self {
+            uvw: Onb::build_from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let cosine = Vec3::dot(Vec3::unit_vector(direction), self.uvw.w);
+        (cosine / PI).max(0.0)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.uvw.local(Vec3::cosine_direction(sampler.get_2d()))
+    }
+}
+
+// Phong 波瓣 概率密度正比于与轴夹角余弦的 exponent 次方 用于粗糙的金属
+// 波瓣的一部分可能落到表面以下 这部分由材质的 scattering_pdf 置为 0
+pub struct PhongPdf {
+    uvw: Onb,
+    exponent: f64,
+}
+
+impl PhongPdf {
+    pub fn new(axis: Vec3, exponent: f64) -> Self {
+        Self {
+            uvw: Onb::build_from_w(axis),
+            exponent,
+        }
+    }
+}
+
+impl Pdf for PhongPdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let cosine = Vec3::dot(Vec3::unit_vector(direction), self.uvw.w);
+        if cosine <= 0.0 {
+            return 0.0;
+        }
+        (self.exponent + 1.0) / (2.0 * PI) * cosine.powf(self.exponent)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let (r1, r2) = sampler.get_2d();
+        let phi = 2.0 * PI * r1;
+        let cosine = r2.powf(1.0 / (self.exponent + 1.0));
+        let sine = (1.0 - cosine * cosine).max(0.0).sqrt();
+        self.uvw
+            .local(Vec3::new(phi.cos() * sine, phi.sin() * sine, cosine))
+    }
+}
+
+// 球面均匀分布 用于各向同性散射
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::sphere_direction(sampler.get_2d())
+    }
+}
+
+// 朝向物体采样 用于光源
+pub struct HittablePdf<'a> {
+    origin: Vec3,
+    object: &'a dyn Hittable,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(object: &'a dyn Hittable, origin: Vec3) -> Self {
+        Self { origin, object }
+    }
+}
+
+impl<'a> Pdf for HittablePdf<'a> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.object.pdf_value(self.origin, direction)
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        self.object.random(self.origin, sampler)
+    }
+}
+
+// 多种分布按权重混合
+pub struct MixturePdf<'a> {
+    p: Vec<(f64, Box<dyn Pdf + 'a>)>,
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(p: Vec<(f64, Box<dyn Pdf + 'a>)>) -> Self {
+        let sum: f64 = p.iter().map(|(weight, _)| weight).sum();
+        Self {
+            p: p.into_iter()
+                .map(|(weight, pdf)| (weight / sum, pdf))
+                .collect(),
+        }
+    }
+}
+
+impl<'a> Pdf for MixturePdf<'a> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.p
+            .iter()
+            .map(|(weight, pdf)| weight * pdf.value(direction))
+            .sum()
+    }
+
+    fn generate(&self, sampler: &mut dyn Sampler) -> Vec3 {
+        let mut r = sampler.gen::<f64>();
+        for (weight, pdf) in &self.p[..self.p.len() - 1] {
+            if r < *weight {
+                return pdf.generate(sampler);
+            }
+            r -= weight;
+        }
+        self.p[self.p.len() - 1].1.generate(sampler)
+    }
+}
+
+// 多重重要性采样的幂启发式权重
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
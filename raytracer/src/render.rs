@@ -0,0 +1,492 @@
+// 渲染调度
+// 图像切成小块 线程池中的线程从共享队列里领取并窃取任务
+
+use crate::camera::Camera;
+use crate::cli::SamplerType;
+use crate::color;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::integrator::Integrator;
+use crate::sampler;
+use crate::stats::{self, Counter, RenderStats, Work};
+use crate::vec3::Color;
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::time::Instant;
+
+// 块的边长
+pub const TILE_SIZE: u32 = 16;
+
+// 图像上的一块 左闭右开 行从图像顶部开始计
+#[derive(Copy, Clone)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+pub fn tiles(width: u32, height: u32) -> Vec<Tile> {
+    let mut result = vec![];
+    for y0 in (0..height).step_by(TILE_SIZE as usize) {
+        for x0 in (0..width).step_by(TILE_SIZE as usize) {
+            result.push(Tile {
+                x0,
+                y0,
+                x1: (x0 + TILE_SIZE).min(width),
+                y1: (y0 + TILE_SIZE).min(height),
+            });
+        }
+    }
+    result
+}
+
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    // 自适应采样时为上限
+    pub samples_per_pixel: u32,
+    pub max_depth: i32,
+    pub seed: u64,
+    pub sampler: SamplerType,
+    // 像素的相对误差低于该值后停止采样 None 表示关闭自适应采样
+    pub error_threshold: Option<f64>,
+    // 检查误差之前至少要有的采样数
+    pub min_samples: u32,
+    pub filter: Box<dyn Filter>,
+}
+
+// 像素的采样统计 用 Welford 方法累计均值与亮度的方差
+#[derive(Copy, Clone)]
+pub struct PixelStats {
+    pub mean: Color,
+    pub m2: f64,
+    pub count: u32,
+}
+
+impl PixelStats {
+    pub fn new() -> Self {
+        Self {
+            mean: Color::zero(),
+            m2: 0.0,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, color: Color) {
+        self.count += 1;
+        let old = color::luminance(self.mean);
+        self.mean += (color - self.mean) / self.count as f64;
+        let new = color::luminance(self.mean);
+        let lum = color::luminance(color);
+        self.m2 += (lum - old) * (lum - new);
+    }
+
+    // 均值的标准误差与均值之比
+    fn error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.count as f64;
+        let std_error = (self.m2 / (n - 1.0) / n).sqrt();
+        std_error / color::luminance(self.mean).max(1e-3)
+    }
+
+    fn converged(&self, settings: &RenderSettings) -> bool {
+        match settings.error_threshold {
+            Some(threshold) => self.count >= settings.min_samples && self.error() < threshold,
+            None => false,
+        }
+    }
+}
+
+impl Default for PixelStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 按滤波器权重累加的颜色
+#[derive(Copy, Clone)]
+pub struct Splat {
+    pub sum: Color,
+    pub weight: f64,
+}
+
+impl Splat {
+    pub fn new() -> Self {
+        Self {
+            sum: Color::zero(),
+            weight: 0.0,
+        }
+    }
+}
+
+impl Default for Splat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 一组的权重和低于应有权重的这个比例时 认为它不可信
+const MIN_WEIGHT: f64 = 0.01;
+
+// 多轮渲染累计的采样 像素按行存放 第 0 行在图像顶部
+// pixels 按采样所在的像素统计 用于自适应采样 splats 是滤波后的图像
+// 第 n 个采样累加到第 n % buckets 组 每个像素的各组相邻存放
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    pub buckets: u32,
+    pub pixels: Vec<PixelStats>,
+    pub splats: Vec<Splat>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32, buckets: u32) -> Self {
+        let buckets = buckets.max(1);
+        // 先转成 usize 再相乘 大图像的组数乘积会超出 u32
+        let pixels = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            buckets,
+            pixels: vec![PixelStats::new(); pixels],
+            splats: vec![Splat::new(); pixels * buckets as usize],
+        }
+    }
+
+    // 输出的胶片总是线性 Rec.709
+    // 只有一组时取平均 否则取各组均值的中位数 少数极亮的采样只影响个别组
+    // Mitchell 与 Lanczos 的负瓣可能让一组的权重和接近 0 甚至为负 这样的组不参与
+    // 各组都不可用时退回像素内采样的平均值
+    pub fn to_film(&self) -> Film {
+        let mut film = Film::new(self.width, self.height);
+        let buckets = self.splats.chunks(self.buckets as usize);
+        for (k, (stats, splats)) in self.pixels.iter().zip(buckets).enumerate() {
+            let k = k as u32;
+            // 每个采样的总权重在 1 左右 按像素的采样数估计一组应有的权重
+            let min_weight = MIN_WEIGHT * (stats.count as f64 / self.buckets as f64).max(1.0);
+            let mut means: Vec<Color> = splats
+                .iter()
+                .filter(|splat| splat.weight > min_weight)
+                .map(|splat| splat.sum / splat.weight)
+                .collect();
+            means.sort_by(|a, b| {
+                color::luminance(*a)
+                    .partial_cmp(&color::luminance(*b))
+                    .unwrap_or(Ordering::Equal)
+            });
+            let n = means.len();
+            let color = if n == 0 {
+                stats.mean
+            } else if n % 2 == 1 {
+                means[n / 2]
+            } else {
+                (means[n / 2 - 1] + means[n / 2]) / 2.0
+            };
+            film.set(k % self.width, k / self.width, color::from_working(color));
+            film.samples[k as usize] = stats.count;
+        }
+        film
+    }
+}
+
+// 一块及其周围滤波器半径内的像素 各线程先累加到自己的块中 再依次合并
+struct SplatTile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    buckets: u32,
+    splats: Vec<Splat>,
+}
+
+impl SplatTile {
+    fn new(tile: Tile, filter: &dyn Filter, width: u32, height: u32, buckets: u32) -> Self {
+        let margin = (filter.radius() + 0.5).ceil() as u32;
+        let (x0, y0) = (
+            tile.x0.saturating_sub(margin),
+            tile.y0.saturating_sub(margin),
+        );
+        let (x1, y1) = (
+            (tile.x1 + margin).min(width),
+            (tile.y1 + margin).min(height),
+        );
+        Self {
+            x0,
+            y0,
+            x1,
+            y1,
+            buckets,
+            splats: vec![Splat::new(); ((x1 - x0) * (y1 - y0) * buckets) as usize],
+        }
+    }
+
+    // (x, y) 是胶片上的连续坐标 像素 (i, j) 的中心在 (i + 0.5, j + 0.5)
+    fn add(&mut self, filter: &dyn Filter, x: f64, y: f64, color: Color, bucket: u32) {
+        let r = filter.radius();
+        let i0 = ((x - 0.5 - r).ceil().max(self.x0 as f64)) as u32;
+        let j0 = ((y - 0.5 - r).ceil().max(self.y0 as f64)) as u32;
+        let i1 = ((x - 0.5 + r).floor() + 1.0).clamp(0.0, self.x1 as f64) as u32;
+        let j1 = ((y - 0.5 + r).floor() + 1.0).clamp(0.0, self.y1 as f64) as u32;
+        let width = self.x1 - self.x0;
+        for j in j0..j1 {
+            let wy = filter.evaluate(j as f64 + 0.5 - y);
+            for i in i0..i1 {
+                let w = filter.evaluate(i as f64 + 0.5 - x) * wy;
+                let pixel = (j - self.y0) * width + i - self.x0;
+                let splat = &mut self.splats[(pixel * self.buckets + bucket) as usize];
+                splat.sum += color * w;
+                splat.weight += w;
+            }
+        }
+    }
+}
+
+// 把块中每个像素的采样数补到 target
+// 第 n 个采样的随机数只由 n 决定 因此分几轮渲染都得到同样的采样
+fn render_tile(
+    integrator: &Integrator,
+    camera: &Camera,
+    settings: &RenderSettings,
+    accumulator: &Accumulator,
+    tile: Tile,
+    target: u32,
+) -> (Vec<PixelStats>, SplatTile) {
+    let filter = settings.filter.as_ref();
+    let mut splats = SplatTile::new(
+        tile,
+        filter,
+        settings.width,
+        settings.height,
+        accumulator.buckets,
+    );
+    let mut result = vec![];
+    for y in tile.y0..tile.y1 {
+        // 光线的 v 从图像底部开始计
+        let j = settings.height - 1 - y;
+        for i in tile.x0..tile.x1 {
+            let pixel = y as u64 * settings.width as u64 + i as u64;
+            let mut stats = accumulator.pixels[pixel as usize];
+            // 误差足够小的像素提前结束
+            while stats.count < target && !stats.converged(settings) {
+                // 每个采样单独播种 结果与线程的调度无关
+                let mut sampler = sampler::create(
+                    settings.sampler,
+                    settings.seed,
+                    pixel,
+                    stats.count,
+                    settings.samples_per_pixel,
+                );
+                // x,y方向分量 加入了多重采样抗锯齿
+                let (du, dv) = sampler.get_2d();
+                let u = (i as f64 + du) / (settings.width - 1) as f64;
+                let v = (j as f64 + dv) / (settings.height - 1) as f64;
+
+                // 生成光线
+                let ray = camera.get_ray(u, v, sampler.as_mut());
+                stats::count(Counter::CameraRays);
+                let color = integrator.ray_color(ray, settings.max_depth, sampler.as_mut());
+                let bucket = stats.count % accumulator.buckets;
+                stats.add(color);
+                splats.add(filter, i as f64 + du, y as f64 + 1.0 - dv, color, bucket);
+            }
+            result.push(stats);
+        }
+    }
+    (result, splats)
+}
+
+// 渲染一轮 每个像素的采样数补到 target
+// stop 返回 true 之后剩下的块不再渲染 返回这一轮是否完整
+// 每完成一块 进度条前进一格 各线程的工作量累加到 stats
+#[allow(clippy::too_many_arguments)]
+pub fn render_pass(
+    integrator: &Integrator,
+    camera: &Camera,
+    settings: &RenderSettings,
+    accumulator: &mut Accumulator,
+    target: u32,
+    stop: &(dyn Fn() -> bool + Sync),
+    progress: &ProgressBar,
+    stats: &mut RenderStats,
+) -> bool {
+    let start = Instant::now();
+    let shared = &*accumulator;
+    let results: Vec<_> = tiles(settings.width, settings.height)
+        .into_par_iter()
+        .filter_map(|tile| {
+            if stop() {
+                return None;
+            }
+            let begin = Instant::now();
+            let result = render_tile(integrator, camera, settings, shared, tile, target);
+            let work = Work {
+                thread: rayon::current_thread_index().unwrap_or(0),
+                busy: begin.elapsed(),
+                counts: stats::take(),
+            };
+            progress.inc(1);
+            Some((tile, result, work))
+        })
+        .collect();
+    stats.elapsed += start.elapsed();
+
+    // 按块的顺序合并 结果与线程的调度无关
+    let complete = results.len() == tiles(settings.width, settings.height).len();
+    for (tile, (pixels, splats), work) in results {
+        stats.add(&work);
+        let tile_width = tile.x1 - tile.x0;
+        for (k, stats) in pixels.into_iter().enumerate() {
+            let k = k as u32;
+            let (x, y) = (tile.x0 + k % tile_width, tile.y0 + k / tile_width);
+            accumulator.pixels[(y * settings.width + x) as usize] = stats;
+        }
+        let splat_width = splats.x1 - splats.x0;
+        let buckets = splats.buckets;
+        for (k, splat) in splats.splats.into_iter().enumerate() {
+            let (k, bucket) = (k as u32 / buckets, k as u32 % buckets);
+            let (x, y) = (splats.x0 + k % splat_width, splats.y0 + k / splat_width);
+            let pixel = y as usize * settings.width as usize + x as usize;
+            let target = &mut accumulator.splats[pixel * buckets as usize + bucket as usize];
+            target.sum += splat.sum;
+            target.weight += splat.weight;
+        }
+    }
+    complete
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{FilterType, Sampling, Termination};
+    use crate::environment::Environment;
+    use crate::filter;
+    use crate::hittable_list::HittableList;
+    use crate::material::Lambertian;
+    use crate::output;
+    use crate::sphere::Sphere;
+    use crate::texture::SolidColor;
+    use crate::vec3::Vec3;
+    use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+    use std::sync::Arc;
+
+    #[test]
+    fn stopped_render_records_completed_samples() {
+        let mut world = HittableList::new();
+        let material = Lambertian::new(SolidColor::new(Color::new(0.5, 0.5, 0.5)));
+        world.add(Box::new(Sphere::new(Vec3::zero(), 1.0, material)));
+        let integrator = Integrator {
+            world,
+            lights: HittableList::new(),
+            environment: Environment::new(Arc::new(SolidColor::new(Color::one()))),
+            sampling: Sampling::Bsdf,
+            termination: Termination::Fixed,
+            rr_depth: 3,
+            clamp_direct: None,
+            clamp_indirect: None,
+        };
+        let camera = Camera::new(
+            (Vec3::new(0.0, 0.0, 5.0), Vec3::zero()),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            0.0,
+            5.0,
+            (0.0, 1.0),
+        );
+        let settings = RenderSettings {
+            width: 8,
+            height: 8,
+            samples_per_pixel: 4,
+            max_depth: 4,
+            seed: 0,
+            sampler: SamplerType::Independent,
+            error_threshold: None,
+            min_samples: 1,
+            filter: filter::create(FilterType::Box, None),
+        };
+        let mut accumulator = Accumulator::new(8, 8, 1);
+        let progress = ProgressBar::hidden();
+        let mut stats = RenderStats::new(1);
+        // 第一轮之后收到中断
+        let interrupted = AtomicBool::new(false);
+        let stop = || interrupted.load(AtomicOrdering::Relaxed);
+        let mut stopped = None;
+        for target in [1, 4] {
+            let complete = render_pass(
+                &integrator,
+                &camera,
+                &settings,
+                &mut accumulator,
+                target,
+                &stop,
+                &progress,
+                &mut stats,
+            );
+            if !complete {
+                stopped = Some("interrupted");
+                break;
+            }
+            interrupted.store(true, AtomicOrdering::Relaxed);
+        }
+        let mut film = accumulator.to_film();
+        film.metadata = film.sample_metadata(settings.samples_per_pixel, stopped);
+
+        let path =
+            std::env::temp_dir().join(format!("raytracer-{}-stopped.exr", std::process::id()));
+        output::write_exr(
+            &path,
+            film.width,
+            film.height,
+            &[("R", &film.channel(0))],
+            &film.metadata,
+        )
+        .unwrap();
+        let image = output::read_exr(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header = |name: &str| {
+            image
+                .metadata
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(header("samplesPerPixel").as_deref(), Some("1"));
+        assert_eq!(header("meanSamplesPerPixel").as_deref(), Some("1.00"));
+        assert_eq!(header("requestedSamplesPerPixel").as_deref(), Some("4"));
+        assert_eq!(header("stopped").as_deref(), Some("interrupted"));
+    }
+
+    #[test]
+    fn to_film_skips_unreliable_buckets() {
+        let mut accumulator = Accumulator::new(2, 1, 3);
+        for (k, stats) in accumulator.pixels.iter_mut().enumerate() {
+            stats.mean = Color::new(0.5, 0.5, 0.5);
+            stats.count = 3;
+            let splats = &mut accumulator.splats[k * 3..k * 3 + 3];
+            splats[0] = Splat {
+                sum: Color::new(1.0, 1.0, 1.0),
+                weight: 1.0,
+            };
+            // 负瓣抵消后权重接近 0 或为负
+            splats[1] = Splat {
+                sum: Color::new(1.0, 1.0, 1.0),
+                weight: 1e-6,
+            };
+            splats[2] = Splat {
+                sum: Color::new(-1.0, -1.0, -1.0),
+                weight: -0.1,
+            };
+        }
+        accumulator.splats[3].weight = 0.0;
+        let film = accumulator.to_film();
+        let first = film.get(0, 0);
+        assert!((first.x - 1.0).abs() < 1e-6);
+        // 没有可用的组时退回像素内采样的平均值
+        let second = film.get(1, 0);
+        assert!((second.x - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,333 @@
+// 采样器
+// 渲染时所有随机数的来源 由种子 像素与采样序号决定 与线程数无关
+// get_2d 按维度给出分布均匀的二维样本 其余随机数直接当作 Rng 使用
+
+use crate::cli::SamplerType;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+pub trait Sampler: RngCore {
+    // 下一个维度上的二维样本 取值在 [0, 1) 内
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+// 每个像素的每个采样各建一个采样器
+pub fn create(
+    sampler_type: SamplerType,
+    seed: u64,
+    pixel: u64,
+    sample: u32,
+    samples_per_pixel: u32,
+) -> Box<dyn Sampler> {
+    // 同一像素的所有采样共用 pixel_seed 低差异序列按它打乱
+    let pixel_seed = mix(seed ^ mix(pixel));
+    let rng = StdRng::seed_from_u64(mix(pixel_seed ^ sample as u64));
+    match sampler_type {
+        SamplerType::Independent => Box::new(IndependentSampler { rng }),
+        SamplerType::Stratified => Box::new(StratifiedSampler {
+            rng,
+            pixel_seed,
+            sample,
+            strata: (samples_per_pixel as f64).sqrt().ceil() as u32,
+            dimension: 0,
+        }),
+        SamplerType::Halton => Box::new(HaltonSampler {
+            rng,
+            pixel_seed,
+            sample,
+            dimension: 0,
+        }),
+        SamplerType::Sobol => Box::new(SobolSampler {
+            rng,
+            pixel_seed,
+            sample,
+            dimension: 0,
+        }),
+    }
+}
+
+macro_rules! impl_rng_core {
+    ($t:ty) => {
+        impl RngCore for $t {
+            fn next_u32(&mut self) -> u32 {
+                self.rng.next_u32()
+            }
+
+            fn next_u64(&mut self) -> u64 {
+                self.rng.next_u64()
+            }
+
+            fn fill_bytes(&mut self, dest: &mut [u8]) {
+                self.rng.fill_bytes(dest)
+            }
+
+            fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+                self.rng.try_fill_bytes(dest)
+            }
+        }
+    };
+}
+
+// 独立均匀采样
+pub struct IndependentSampler {
+    rng: StdRng,
+}
+
+impl_rng_core!(IndependentSampler);
+
+impl Sampler for IndependentSampler {
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+// 分层采样 每个维度把单位正方形分成 strata x strata 格
+// 各维度按不同的排列选格 以免维度之间相关
+pub struct StratifiedSampler {
+    rng: StdRng,
+    pixel_seed: u64,
+    sample: u32,
+    strata: u32,
+    dimension: u64,
+}
+
+impl_rng_core!(StratifiedSampler);
+
+impl Sampler for StratifiedSampler {
+    fn get_2d(&mut self) -> (f64, f64) {
+        let count = self.strata * self.strata;
+        let seed = mix(self.pixel_seed ^ mix(self.dimension)) as u32;
+        self.dimension += 1;
+        let stratum = permute(self.sample % count, count, seed);
+        let n = self.strata as f64;
+        (
+            ((stratum % self.strata) as f64 + self.rng.gen::<f64>()) / n,
+            ((stratum / self.strata) as f64 + self.rng.gen::<f64>()) / n,
+        )
+    }
+}
+
+// Halton 序列 每个维度用一对素数做底
+// 按像素对各位数字做 Owen 扰乱 避免相邻像素得到同样的样本 也改善大素数维度的投影
+pub struct HaltonSampler {
+    rng: StdRng,
+    pixel_seed: u64,
+    sample: u32,
+    dimension: u64,
+}
+
+impl_rng_core!(HaltonSampler);
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+impl Sampler for HaltonSampler {
+    fn get_2d(&mut self) -> (f64, f64) {
+        let d = self.dimension as usize;
+        self.dimension += 1;
+        // 素数用完之后退回独立采样
+        if 2 * d + 1 >= PRIMES.len() {
+            return (self.rng.gen(), self.rng.gen());
+        }
+        let seed = mix(self.pixel_seed ^ mix(d as u64));
+        (
+            scrambled_radical_inverse(PRIMES[2 * d], self.sample, seed),
+            scrambled_radical_inverse(PRIMES[2 * d + 1], self.sample, mix(seed)),
+        )
+    }
+}
+
+// Owen 扰乱的 Sobol 序列
+// 每个维度都用 Sobol 的前两维 并按维度打乱采样序号与数位
+// 见 Burley, Practical Hash-based Owen Scrambling, 2020
+pub struct SobolSampler {
+    rng: StdRng,
+    pixel_seed: u64,
+    sample: u32,
+    dimension: u64,
+}
+
+impl_rng_core!(SobolSampler);
+
+impl Sampler for SobolSampler {
+    fn get_2d(&mut self) -> (f64, f64) {
+        let seed = mix(self.pixel_seed ^ mix(self.dimension));
+        self.dimension += 1;
+        let index = nested_uniform_scramble(self.sample, seed as u32);
+        let x = nested_uniform_scramble(sobol_0(index), (seed >> 32) as u32);
+        let y = nested_uniform_scramble(sobol_1(index), mix(seed) as u32);
+        (to_unit(x), to_unit(y))
+    }
+}
+
+// splitmix64 的混合函数 把相邻的整数打散
+pub fn mix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / (1u64 << 32) as f64
+}
+
+// 由 seed 决定的 0..len 的排列中第 i 个元素
+// 见 Kensler, Correlated Multi-Jittered Sampling, 2013
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            return i.wrapping_add(seed) % len;
+        }
+    }
+}
+
+// 各位数字按前面的数字决定的排列打乱后的 radical inverse
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv = inv_base;
+    let mut result = 0.0;
+    let mut prefix = seed;
+    // 超过 32 位精度的数字不再有意义
+    while inv > 1e-10 {
+        let digit = index % base;
+        index /= base;
+        result += permute(digit, base, prefix as u32) as f64 * inv;
+        prefix = mix(prefix ^ digit as u64);
+        inv *= inv_base;
+    }
+    result.min(1.0 - f64::EPSILON)
+}
+
+// Sobol 序列的第一维 即以 2 为底的 radical inverse
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+// Sobol 序列的第二维 方向数满足 v[i] = v[i - 1] ^ (v[i - 1] >> 1)
+fn sobol_1(mut index: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    while index > 0 {
+        if index & 1 == 1 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+// 以 2 为底逐位的 Owen 扰乱
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const SAMPLES: u32 = 64;
+    const PIXELS: u64 = 256;
+
+    // 各像素用 SAMPLES 个样本估计 sin(pi u) v^2 在单位正方形上的积分 返回均方根误差
+    fn rmse(sampler_type: SamplerType, dimension: usize) -> f64 {
+        let exact = 2.0 / PI / 3.0;
+        let sum: f64 = (0..PIXELS)
+            .map(|pixel| {
+                let estimate = (0..SAMPLES)
+                    .map(|sample| {
+                        let mut sampler = create(sampler_type, 1, pixel, sample, SAMPLES);
+                        for _ in 0..dimension {
+                            sampler.get_2d();
+                        }
+                        let (u, v) = sampler.get_2d();
+                        assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v));
+                        (PI * u).sin() * v * v
+                    })
+                    .sum::<f64>()
+                    / SAMPLES as f64;
+                (estimate - exact).powi(2)
+            })
+            .sum();
+        (sum / PIXELS as f64).sqrt()
+    }
+
+    #[test]
+    fn low_discrepancy_converges_faster() {
+        for &dimension in &[0, 3] {
+            let independent = rmse(SamplerType::Independent, dimension);
+            for &sampler_type in &[
+                SamplerType::Stratified,
+                SamplerType::Halton,
+                SamplerType::Sobol,
+            ] {
+                let error = rmse(sampler_type, dimension);
+                assert!(
+                    error * 2.0 < independent,
+                    "dimension {}: {} vs independent {}",
+                    dimension,
+                    error,
+                    independent
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn permute_is_a_permutation() {
+        for &len in &[1, 2, 3, 7, 16, 100] {
+            let mut seen: Vec<u32> = (0..len).map(|i| permute(i, len, 12345)).collect();
+            seen.sort_unstable();
+            assert_eq!(seen, (0..len).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn same_seed_same_samples() {
+        let mut a = create(SamplerType::Sobol, 7, 3, 5, 16);
+        let mut b = create(SamplerType::Sobol, 7, 3, 5, 16);
+        for _ in 0..4 {
+            assert_eq!(a.get_2d(), b.get_2d());
+        }
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+}
@@ -0,0 +1,177 @@
+// 场景
+// 物体列表 背景与默认镜头参数
+
+use crate::bvh_node::BVHNode;
+use crate::hittable::generator;
+use crate::hittable_list::HittableList;
+use crate::mc_world::{self, Block};
+use crate::texture::*;
+use crate::vec3::{Color, Vec3};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::Arc;
+
+pub struct Scene {
+    pub world: HittableList,
+    // 需要显式采样的光源
+    pub lights: HittableList,
+    pub background: Arc<dyn Texture>,
+    pub lookfrom: Vec3,
+    pub lookat: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+}
+
+// 可选场景 名称与说明
+pub const SCENES: [(&str, &str); 12] = [
+    ("random_scene", "random spheres on a checker ground"),
+    ("two_spheres", "two checker spheres"),
+    ("two_perlin_spheres", "two spheres with perlin noise"),
+    ("earth", "earth map texture"),
+    ("simple_light", "perlin spheres lit by a rect light"),
+    ("cornell_box", "the cornell box"),
+    ("cornell_smoke", "cornell box with smoke blocks"),
+    ("final_scene", "everything from the second book"),
+    ("triangles", "triangles with uv test texture"),
+    ("obj_cat", "cat loaded from an obj file"),
+    ("the_world", "the pixel world"),
+    ("forest", "ten thousand instanced trees"),
+];
+
+impl Scene {
+    fn new(
+        world: HittableList,
+        background: Arc<dyn Texture>,
+        lookfrom: Vec3,
+        lookat: Vec3,
+    ) -> Self {
+        Self {
+            world,
+            lights: HittableList::new(),
+            background,
+            lookfrom,
+            lookat,
+            vfov: 20.0,
+            aperture: 0.0,
+            focus_dist: 40.0,
+        }
+    }
+
+    // 顶层物体按顺序编号
+    fn bvh(list: HittableList) -> HittableList {
+        HittableList {
+            objects: vec![BVHNode::create(list.tagged(), 0.0, 1.0)],
+        }
+    }
+
+    // seed 决定随机生成的场景内容
+    pub fn load(name: &str, seed: u64) -> Option<Self> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let sky: Arc<dyn Texture> = Arc::new(SolidColor::new(Color::new(0.7, 0.8, 1.0)));
+        let dark: Arc<dyn Texture> = Arc::new(SolidColor::new(Color::zero()));
+        let mut lights = HittableList::new();
+        let scene = match name {
+            "random_scene" => Self {
+                aperture: 0.1,
+                ..Self::new(
+                    Self::bvh(generator::random_scene(&mut rng)),
+                    Arc::new(HdrImageTexture::new(
+                        "raytracer/src/texture/img/Path_Env.hdr".to_string(),
+                        0.7,
+                    )),
+                    Vec3::new(13.0, 2.0, 3.0),
+                    Vec3::zero(),
+                )
+            },
+            "two_spheres" => Self::new(
+                Self::bvh(generator::two_spheres()),
+                sky,
+                Vec3::new(13.0, 2.0, 3.0),
+                Vec3::zero(),
+            ),
+            "two_perlin_spheres" => Self::new(
+                Self::bvh(generator::two_perlin_spheres(&mut rng)),
+                sky,
+                Vec3::new(13.0, 2.0, 3.0),
+                Vec3::zero(),
+            ),
+            "earth" => Self::new(
+                Self::bvh(generator::earth()),
+                sky,
+                Vec3::new(13.0, 2.0, 3.0),
+                Vec3::zero(),
+            ),
+            "simple_light" => Self::new(
+                Self::bvh(generator::simple_light(&mut lights, &mut rng)),
+                dark,
+                Vec3::new(26.0, 3.0, 6.0),
+                Vec3::new(0.0, 2.0, 0.0),
+            ),
+            "cornell_box" => Self {
+                vfov: 40.0,
+                ..Self::new(
+                    Self::bvh(generator::cornell_box(&mut lights)),
+                    dark,
+                    Vec3::new(278.0, 278.0, -800.0),
+                    Vec3::new(278.0, 278.0, 0.0),
+                )
+            },
+            "cornell_smoke" => Self {
+                vfov: 40.0,
+                ..Self::new(
+                    Self::bvh(generator::cornell_smoke(&mut lights)),
+                    dark,
+                    Vec3::new(278.0, 278.0, -800.0),
+                    Vec3::new(278.0, 278.0, 0.0),
+                )
+            },
+            "final_scene" => Self {
+                vfov: 40.0,
+                ..Self::new(
+                    generator::final_scene(&mut lights, &mut rng),
+                    dark,
+                    Vec3::new(478.0, 278.0, -600.0),
+                    Vec3::new(278.0, 278.0, 0.0),
+                )
+            },
+            "triangles" => Self::new(
+                generator::triangles().tagged(),
+                sky,
+                Vec3::new(0.0, 0.0, 10.0),
+                Vec3::zero(),
+            ),
+            "obj_cat" => Self::new(
+                generator::obj_cat().tagged(),
+                Arc::new(ImageTexture::new(
+                    "raytracer/src/texture/img/earthmap.jpg".to_string(),
+                )),
+                Vec3::new(1000.0, 500.0, 1000.0),
+                Vec3::new(0.0, 200.0, 0.0),
+            ),
+            "the_world" => Self {
+                vfov: 40.0,
+                aperture: 0.2,
+                ..Self::new(
+                    Block::the_world(&mut lights, &mut rng),
+                    Arc::new(ImageTexture::new(
+                        "raytracer/src/texture/img/twilight.jpg".to_string(),
+                    )),
+                    Vec3::new(10.0, 10.0, 10.0),
+                    Vec3::new(36.0, 2.0, 36.0),
+                )
+            },
+            "forest" => Self {
+                vfov: 40.0,
+                ..Self::new(
+                    Self::bvh(mc_world::forest(&mut rng)),
+                    sky,
+                    Vec3::new(-60.0, 80.0, -60.0),
+                    Vec3::new(150.0, 0.0, 150.0),
+                )
+            },
+            _ => return None,
+        };
+        Some(Self { lights, ..scene })
+    }
+}
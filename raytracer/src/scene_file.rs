@@ -0,0 +1,775 @@
+// 场景文件
+// 从 JSON 或 TOML 描述构造物体 材质 纹理与镜头
+// 具名的纹理与材质可在多处复用
+
+use crate::bvh_node::BVHNode;
+use crate::color::ColorSpace;
+use crate::hittable::aarect::*;
+use crate::hittable::constant_medium::ConstantMedium;
+use crate::hittable::hittable_list::HittableList;
+use crate::hittable::instance::*;
+use crate::hittable::moving_sphere::MovingSphere;
+use crate::hittable::rectbox::RectBox;
+use crate::hittable::sphere::Sphere;
+use crate::hittable::triangle::Triangle;
+use crate::hittable::Hittable;
+use crate::material::*;
+use crate::matrix::Matrix4;
+use crate::obj_file;
+use crate::scene::Scene;
+use crate::texture::*;
+use crate::vec3::Vec3;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    UnknownFormat(String),
+    File {
+        file: String,
+        message: String,
+    },
+    UnknownTexture(String),
+    UnknownMaterial(String),
+    UnknownGeometry(String),
+    Recursive(String),
+    SingularTransform,
+    BadTransform(&'static str),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "{}", err),
+            SceneError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "line {} column {}: {}", line, column, message),
+            SceneError::UnknownFormat(ext) => {
+                write!(f, "unknown scene format \"{}\", use .json or .toml", ext)
+            }
+            SceneError::File { file, message } => {
+                write!(f, "cannot load \"{}\": {}", file, message)
+            }
+            SceneError::UnknownTexture(name) => write!(f, "unknown texture \"{}\"", name),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material \"{}\"", name),
+            SceneError::UnknownGeometry(name) => write!(f, "unknown geometry \"{}\"", name),
+            SceneError::Recursive(name) => write!(f, "{} is defined by itself", name),
+            SceneError::SingularTransform => write!(f, "transform is not invertible"),
+            SceneError::BadTransform(reason) => write!(f, "invalid transform: {}", reason),
+        }
+    }
+}
+
+type Point = [f64; 3];
+
+fn vec3(p: Point) -> Vec3 {
+    Vec3::new(p[0], p[1], p[2])
+}
+
+#[derive(Deserialize)]
+struct SceneDesc {
+    #[serde(default)]
+    camera: CameraDesc,
+    #[serde(default = "black")]
+    background: TextureRef,
+    #[serde(default)]
+    textures: HashMap<String, TextureRef>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialRef>,
+    // 具名几何体 只建一次BVH 由 instance 引用
+    #[serde(default)]
+    geometry: HashMap<String, Vec<ObjectDesc>>,
+    objects: Vec<ObjectDesc>,
+    #[serde(default = "yes")]
+    bvh: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct CameraDesc {
+    lookfrom: Point,
+    lookat: Point,
+    vfov: f64,
+    aperture: f64,
+    focus_dist: f64,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            lookfrom: [13.0, 2.0, 3.0],
+            lookat: [0.0, 0.0, 0.0],
+            vfov: 20.0,
+            aperture: 0.0,
+            focus_dist: 40.0,
+        }
+    }
+}
+
+// 纹理 可以是名称 颜色 或完整定义
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureRef {
+    Name(String),
+    Color(Point),
+    Def(Box<TextureDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TextureDesc {
+    Solid {
+        color: Point,
+    },
+    Checker {
+        odd: TextureRef,
+        even: TextureRef,
+    },
+    Noise {
+        scale: f64,
+    },
+    Image {
+        file: String,
+        #[serde(default)]
+        color_space: ColorSpace,
+    },
+    Hdr {
+        file: String,
+        limit: f64,
+    },
+}
+
+// 材质 可以是名称或完整定义
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaterialRef {
+    Name(String),
+    Def(Box<MaterialDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: Point,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        ir: f64,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    ColoredDielectric {
+        ir: f64,
+        #[serde(default)]
+        fuzz: f64,
+        color: Point,
+    },
+    DiffuseLight {
+        emit: TextureRef,
+    },
+    Isotropic {
+        albedo: TextureRef,
+    },
+}
+
+#[derive(Deserialize)]
+struct ObjectDesc {
+    #[serde(flatten)]
+    shape: ShapeDesc,
+    // 按顺序施加的变换
+    #[serde(default)]
+    transform: Vec<TransformDesc>,
+    // 是否作为光源显式采样 仅对顶层物体有效
+    #[serde(default)]
+    light: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ShapeDesc {
+    Sphere {
+        center: Point,
+        radius: f64,
+        material: MaterialRef,
+    },
+    MovingSphere {
+        center0: Point,
+        center1: Point,
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "one")]
+        time1: f64,
+        radius: f64,
+        material: MaterialRef,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    Box {
+        min: Point,
+        max: Point,
+        material: MaterialRef,
+    },
+    Triangle {
+        a: Point,
+        b: Point,
+        c: Point,
+        material: MaterialRef,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDesc>,
+        density: f64,
+        material: MaterialRef,
+    },
+    Obj {
+        file: String,
+        material: MaterialRef,
+        #[serde(default = "one")]
+        scale: f64,
+    },
+    // 引用具名几何体 可以替换全部材质
+    Instance {
+        geometry: String,
+        #[serde(default)]
+        material: Option<MaterialRef>,
+    },
+    List {
+        objects: Vec<ObjectDesc>,
+        #[serde(default)]
+        bvh: bool,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TransformDesc {
+    Translate(Point),
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Rotate {
+        axis: Point,
+        angle: f64,
+    },
+    Scale(ScaleDesc),
+    LookAt {
+        from: Point,
+        to: Point,
+        #[serde(default = "up")]
+        up: Point,
+    },
+    // 按行书写的 4x4 矩阵 可以表示错切
+    Matrix([[f64; 4]; 4]),
+}
+
+// 均匀缩放或三个轴分别缩放
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes(Point),
+}
+
+// 按顺序施加的变换合成一个矩阵
+fn matrix(transforms: &[TransformDesc]) -> Result<Matrix4, SceneError> {
+    let mut matrix = Matrix4::identity();
+    for transform in transforms.iter() {
+        matrix = match transform {
+            TransformDesc::Translate(offset) => matrix.translate(vec3(*offset)),
+            TransformDesc::RotateX(angle) => matrix.rotate(Vec3::new(1.0, 0.0, 0.0), *angle),
+            TransformDesc::RotateY(angle) => matrix.rotate(Vec3::new(0.0, 1.0, 0.0), *angle),
+            TransformDesc::RotateZ(angle) => matrix.rotate(Vec3::new(0.0, 0.0, 1.0), *angle),
+            TransformDesc::Rotate { axis, angle } => {
+                if vec3(*axis).length_squared() == 0.0 {
+                    return Err(SceneError::BadTransform("rotation axis is zero"));
+                }
+                matrix.rotate(vec3(*axis), *angle)
+            }
+            TransformDesc::Scale(ScaleDesc::Uniform(scale)) => matrix.scale(*scale),
+            TransformDesc::Scale(ScaleDesc::Axes(scale)) => matrix.scale_xyz(vec3(*scale)),
+            TransformDesc::LookAt { from, to, up } => {
+                let (from, to, up) = (vec3(*from), vec3(*to), vec3(*up));
+                let direction = to - from;
+                if direction.length_squared() == 0.0 {
+                    return Err(SceneError::BadTransform("look_at from and to are the same"));
+                }
+                // 叉积相对两者长度很小时视为平行 包括 up 为零向量
+                let side = Vec3::cross(up, direction).length();
+                if side <= 1e-9 * up.length() * direction.length() {
+                    return Err(SceneError::BadTransform(
+                        "look_at up is parallel to the view direction",
+                    ));
+                }
+                matrix.look_at(from, to, up)
+            }
+            TransformDesc::Matrix(m) => {
+                // 只支持仿射变换 透视变换不能作用于物体
+                if m[3] != [0.0, 0.0, 0.0, 1.0] {
+                    return Err(SceneError::BadTransform(
+                        "the last row of a matrix must be [0, 0, 0, 1]",
+                    ));
+                }
+                matrix.then(Matrix4 { m: *m })
+            }
+        };
+    }
+    if matrix.inverse().is_none() {
+        return Err(SceneError::SingularTransform);
+    }
+    Ok(matrix)
+}
+
+fn black() -> TextureRef {
+    TextureRef::Color([0.0, 0.0, 0.0])
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn up() -> Point {
+    [0.0, 1.0, 0.0]
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+struct Builder<'a> {
+    texture_descs: &'a HashMap<String, TextureRef>,
+    material_descs: &'a HashMap<String, MaterialRef>,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    geometry_descs: &'a HashMap<String, Vec<ObjectDesc>>,
+    geometry: HashMap<String, Arc<dyn Hittable>>,
+    // 同一 OBJ 文件以同样的比例只加载一次
+    meshes: HashMap<String, Arc<dyn Hittable>>,
+    // 正在构造的名称 用于检查循环引用
+    pending: Vec<String>,
+    // 噪声纹理的随机数
+    rng: StdRng,
+}
+
+impl<'a> Builder<'a> {
+    fn named<T: ?Sized>(
+        &mut self,
+        kind: &str,
+        name: &str,
+        built: fn(&mut Self) -> &mut HashMap<String, Arc<T>>,
+        build: impl FnOnce(&mut Self) -> Result<Arc<T>, SceneError>,
+    ) -> Result<Arc<T>, SceneError> {
+        if let Some(value) = built(self).get(name) {
+            return Ok(value.clone());
+        }
+        let key = format!("{} {}", kind, name);
+        if self.pending.contains(&key) {
+            return Err(SceneError::Recursive(key));
+        }
+        self.pending.push(key);
+        let value = build(self)?;
+        self.pending.pop();
+        built(self).insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    fn texture(&mut self, desc: &TextureRef) -> Result<Arc<dyn Texture>, SceneError> {
+        Ok(match desc {
+            TextureRef::Name(name) => {
+                let descs = self.texture_descs;
+                let desc = descs
+                    .get(name)
+                    .ok_or_else(|| SceneError::UnknownTexture(name.clone()))?;
+                self.named("texture", name, |b| &mut b.textures, |b| b.texture(desc))?
+            }
+            TextureRef::Color(color) => Arc::new(SolidColor::new(vec3(*color))),
+            TextureRef::Def(def) => match def.as_ref() {
+                TextureDesc::Solid { color } => Arc::new(SolidColor::new(vec3(*color))),
+                TextureDesc::Checker { odd, even } => {
+                    let even = self.texture(even)?;
+                    Arc::new(CheckerTexture::new(even, self.texture(odd)?))
+                }
+                TextureDesc::Noise { scale } => Arc::new(NoiseTexture::new(*scale, &mut self.rng)),
+                TextureDesc::Image { file, color_space } => Arc::new(
+                    ImageTexture::open(file, *color_space).map_err(|err| file_error(file, err))?,
+                ),
+                TextureDesc::Hdr { file, limit } => Arc::new(
+                    HdrImageTexture::open(file, *limit).map_err(|err| file_error(file, err))?,
+                ),
+            },
+        })
+    }
+
+    fn material(&mut self, desc: &MaterialRef) -> Result<Arc<dyn Material>, SceneError> {
+        Ok(match desc {
+            MaterialRef::Name(name) => {
+                let descs = self.material_descs;
+                let desc = descs
+                    .get(name)
+                    .ok_or_else(|| SceneError::UnknownMaterial(name.clone()))?;
+                self.named("material", name, |b| &mut b.materials, |b| b.material(desc))?
+            }
+            MaterialRef::Def(def) => match def.as_ref() {
+                MaterialDesc::Lambertian { albedo } => {
+                    Arc::new(Lambertian::new(self.texture(albedo)?))
+                }
+                MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(vec3(*albedo), *fuzz)),
+                MaterialDesc::Dielectric { ir, fuzz } => Arc::new(Dielectric::new(*ir, *fuzz)),
+                MaterialDesc::ColoredDielectric { ir, fuzz, color } => {
+                    Arc::new(ColoredDielectric::new(*ir, *fuzz, vec3(*color)))
+                }
+                MaterialDesc::DiffuseLight { emit } => {
+                    Arc::new(DiffuseLight::new(self.texture(emit)?))
+                }
+                MaterialDesc::Isotropic { albedo } => {
+                    Arc::new(Isotropic::new(self.texture(albedo)?))
+                }
+            },
+        })
+    }
+
+    fn object(&mut self, desc: &ObjectDesc) -> Result<Box<dyn Hittable>, SceneError> {
+        let mut object: Box<dyn Hittable> = match &desc.shape {
+            ShapeDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere::new(
+                vec3(*center),
+                *radius,
+                self.material(material)?,
+            )),
+            ShapeDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => Box::new(MovingSphere::new(
+                vec3(*center0),
+                vec3(*center1),
+                *time0,
+                *time1,
+                *radius,
+                self.material(material)?,
+            )),
+            ShapeDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => Box::new(XYRect::new(
+                *x0,
+                *x1,
+                *y0,
+                *y1,
+                *k,
+                self.material(material)?,
+            )),
+            ShapeDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(XZRect::new(
+                *x0,
+                *x1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material)?,
+            )),
+            ShapeDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(YZRect::new(
+                *y0,
+                *y1,
+                *z0,
+                *z1,
+                *k,
+                self.material(material)?,
+            )),
+            ShapeDesc::Box { min, max, material } => Box::new(RectBox::new(
+                vec3(*min),
+                vec3(*max),
+                self.material(material)?,
+            )),
+            ShapeDesc::Triangle { a, b, c, material } => Box::new(Triangle::new(
+                vec3(*a),
+                vec3(*b),
+                vec3(*c),
+                self.material(material)?,
+            )),
+            ShapeDesc::ConstantMedium {
+                boundary,
+                density,
+                material,
+            } => Box::new(ConstantMedium::new(
+                self.object(boundary)?,
+                *density,
+                self.material(material)?,
+            )),
+            ShapeDesc::Obj {
+                file,
+                material,
+                scale,
+            } => {
+                // 网格只用一种材质 共享时用各自的材质替换
+                let material = self.material(material)?;
+                let key = format!("{}@{}", file, scale);
+                let mesh = match self.meshes.get(&key) {
+                    Some(mesh) => mesh.clone(),
+                    None => {
+                        let objects = obj_file::try_load(file.clone(), material.clone(), *scale)
+                            .map_err(|err| file_error(file, err))?;
+                        let mesh = blas(HittableList { objects });
+                        self.meshes.insert(key, mesh.clone());
+                        mesh
+                    }
+                };
+                let instance = Instance::new(mesh, Matrix4::identity(), Some(material));
+                Box::new(instance.expect("identity matrix is invertible"))
+            }
+            ShapeDesc::Instance { geometry, material } => {
+                let descs = self.geometry_descs;
+                let desc_list = descs
+                    .get(geometry)
+                    .ok_or_else(|| SceneError::UnknownGeometry(geometry.clone()))?;
+                let blas = self.named(
+                    "geometry",
+                    geometry,
+                    |b| &mut b.geometry,
+                    |b| Ok(blas(b.list(desc_list)?)),
+                )?;
+                let material = match material {
+                    Some(material) => Some(self.material(material)?),
+                    None => None,
+                };
+                // 变换直接并入实例
+                let instance = Instance::new(blas, matrix(&desc.transform)?, material)
+                    .ok_or(SceneError::SingularTransform)?;
+                return Ok(Box::new(instance));
+            }
+            ShapeDesc::List { objects, bvh } => {
+                let list = self.list(objects)?;
+                if *bvh && !list.objects.is_empty() {
+                    BVHNode::create(list, 0.0, 1.0)
+                } else {
+                    Box::new(list)
+                }
+            }
+        };
+        if !desc.transform.is_empty() {
+            object = Box::new(
+                Transform::new(object, matrix(&desc.transform)?)
+                    .ok_or(SceneError::SingularTransform)?,
+            );
+        }
+        Ok(object)
+    }
+
+    fn list(&mut self, objects: &[ObjectDesc]) -> Result<HittableList, SceneError> {
+        let mut list = HittableList::new();
+        for desc in objects.iter() {
+            list.add(self.object(desc)?);
+        }
+        Ok(list)
+    }
+}
+
+fn file_error(file: &str, err: impl fmt::Display) -> SceneError {
+    SceneError::File {
+        file: file.to_string(),
+        message: err.to_string(),
+    }
+}
+
+// 解析错误统一报告行列号 从 1 开始
+fn parse_error(line: usize, column: usize, err: impl fmt::Display) -> SceneError {
+    let suffix = format!(" at line {} column {}", line, column);
+    SceneError::Parse {
+        line,
+        column,
+        message: err.to_string().replace(&suffix, ""),
+    }
+}
+
+fn parse(path: &Path, text: &str) -> Result<SceneDesc, SceneError> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match ext.as_str() {
+        "json" => {
+            serde_json::from_str(text).map_err(|err| parse_error(err.line(), err.column(), err))
+        }
+        "toml" => toml::from_str(text).map_err(|err| {
+            let (line, column) = err.line_col().map_or((0, 0), |(l, c)| (l + 1, c + 1));
+            parse_error(line, column, err)
+        }),
+        _ => Err(SceneError::UnknownFormat(ext)),
+    }
+}
+
+pub fn load(path: &Path, seed: u64) -> Result<Scene, SceneError> {
+    let text = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+    let desc = parse(path, &text)?;
+
+    // 具名纹理与材质在首次引用时构造
+    let mut builder = Builder {
+        texture_descs: &desc.textures,
+        material_descs: &desc.materials,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        geometry_descs: &desc.geometry,
+        geometry: HashMap::new(),
+        meshes: HashMap::new(),
+        pending: vec![],
+        rng: StdRng::seed_from_u64(seed),
+    };
+    // 光源与场景共用同一个物体 不重复构造
+    let mut list = HittableList::new();
+    let mut lights = HittableList::new();
+    for object_desc in desc.objects.iter() {
+        let object = builder.object(object_desc)?;
+        if object_desc.light {
+            let object: Arc<dyn Hittable> = Arc::from(object);
+            lights.add(Box::new(object.clone()));
+            list.add(Box::new(object));
+        } else {
+            list.add(object);
+        }
+    }
+    let list = list.tagged();
+    let world = if desc.bvh && !list.objects.is_empty() {
+        HittableList {
+            objects: vec![BVHNode::create(list, 0.0, 1.0)],
+        }
+    } else {
+        list
+    };
+
+    Ok(Scene {
+        world,
+        lights,
+        background: builder.texture(&desc.background)?,
+        lookfrom: vec3(desc.camera.lookfrom),
+        lookat: vec3(desc.camera.lookat),
+        vfov: desc.camera.vfov,
+        aperture: desc.camera.aperture,
+        focus_dist: desc.camera.focus_dist,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(transform: TransformDesc) -> bool {
+        matches!(matrix(&[transform]), Err(SceneError::BadTransform(_)))
+    }
+
+    #[test]
+    fn rejects_degenerate_transforms() {
+        assert!(invalid(TransformDesc::Rotate {
+            axis: [0.0, 0.0, 0.0],
+            angle: 30.0,
+        }));
+        assert!(invalid(TransformDesc::LookAt {
+            from: [1.0, 2.0, 3.0],
+            to: [1.0, 2.0, 3.0],
+            up: up(),
+        }));
+        assert!(invalid(TransformDesc::LookAt {
+            from: [0.0, 0.0, 0.0],
+            to: [0.0, 5.0, 0.0],
+            up: up(),
+        }));
+        assert!(invalid(TransformDesc::LookAt {
+            from: [0.0, 0.0, 0.0],
+            to: [1.0, 0.0, 0.0],
+            up: [0.0, 0.0, 0.0],
+        }));
+        assert!(invalid(TransformDesc::Matrix([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.5, 1.0],
+        ])));
+        assert!(matches!(
+            matrix(&[TransformDesc::Scale(ScaleDesc::Axes([1.0, 0.0, 1.0]))]),
+            Err(SceneError::SingularTransform)
+        ));
+    }
+
+    #[test]
+    fn accepts_valid_transforms() {
+        let transforms = [
+            TransformDesc::Rotate {
+                axis: [1.0, 1.0, 0.0],
+                angle: 45.0,
+            },
+            TransformDesc::LookAt {
+                from: [0.0, 1.0, 0.0],
+                to: [5.0, 1.0, 5.0],
+                up: up(),
+            },
+            TransformDesc::Matrix([
+                [1.0, 0.5, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]),
+        ];
+        assert!(matrix(&transforms).is_ok());
+    }
+}
@@ -0,0 +1,181 @@
+// 渲染统计
+// 计数器是各线程自己的局部变量 渲染完一块后取出随结果返回 由主线程依次累加
+// 线程之间不共享任何计数器 计数不需要加锁或原子操作
+
+use crate::bvh_node::TreeQuality;
+use console::style;
+use serde_json::json;
+use std::cell::Cell;
+use std::time::Duration;
+
+#[derive(Copy, Clone)]
+pub enum Counter {
+    CameraRays,
+    BounceRays,
+    ShadowRays,
+    AabbTests,
+    PrimitiveHits,
+    BvhNodes,
+}
+
+const COUNTERS: usize = 6;
+
+// 报告中的名称 与 Counter 的顺序相同
+const NAMES: [(&str, &str); COUNTERS] = [
+    ("camera_rays", "Camera rays"),
+    ("bounce_rays", "Bounce rays"),
+    ("shadow_rays", "Shadow rays"),
+    ("aabb_tests", "Aabb tests"),
+    ("primitive_hits", "Primitive hits"),
+    ("bvh_nodes", "BVH nodes visited"),
+];
+
+thread_local! {
+    static LOCAL: [Cell<u64>; COUNTERS] = Default::default();
+}
+
+pub fn count(counter: Counter) {
+    LOCAL.with(|local| {
+        let cell = &local[counter as usize];
+        cell.set(cell.get() + 1);
+    });
+}
+
+// 取出当前线程的计数并清零
+pub fn take() -> [u64; COUNTERS] {
+    LOCAL.with(|local| {
+        let mut counts = [0; COUNTERS];
+        for (count, cell) in counts.iter_mut().zip(local) {
+            *count = cell.replace(0);
+        }
+        counts
+    })
+}
+
+// 一个线程渲染一块的工作量
+pub struct Work {
+    // 线程池中的编号
+    pub thread: usize,
+    pub busy: Duration,
+    pub counts: [u64; COUNTERS],
+}
+
+pub struct RenderStats {
+    pub counts: [u64; COUNTERS],
+    // 每个线程渲染块的总时间
+    pub busy: Vec<Duration>,
+    // 各轮渲染的总时间 减去 busy 即为线程空闲的时间
+    pub elapsed: Duration,
+    // 场景中各棵 BVH 的质量 在渲染之前建好
+    pub bvh: TreeQuality,
+}
+
+impl RenderStats {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            counts: [0; COUNTERS],
+            busy: vec![Duration::ZERO; threads],
+            elapsed: Duration::ZERO,
+            bvh: TreeQuality::default(),
+        }
+    }
+
+    pub fn add(&mut self, work: &Work) {
+        for (total, count) in self.counts.iter_mut().zip(work.counts) {
+            *total += count;
+        }
+        if work.thread >= self.busy.len() {
+            self.busy.resize(work.thread + 1, Duration::ZERO);
+        }
+        self.busy[work.thread] += work.busy;
+    }
+
+    fn rays(&self) -> u64 {
+        let [camera, bounce, shadow, ..] = self.counts;
+        camera + bounce + shadow
+    }
+
+    fn rays_per_second(&self) -> f64 {
+        self.rays() as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    fn idle(&self, busy: Duration) -> Duration {
+        self.elapsed.saturating_sub(busy)
+    }
+
+    pub fn print(&self) {
+        println!("{}", style("Render statistics").bold());
+        println!(
+            "  {:<20} {:.2} s",
+            "Render time",
+            self.elapsed.as_secs_f64()
+        );
+        for ((_, name), count) in NAMES.iter().zip(self.counts) {
+            println!("  {:<20} {}", name, si(count as f64));
+        }
+        println!("  {:<20} {}/s", "Rays", si(self.rays_per_second()));
+        println!(
+            "  {:<20} {} trees, {} nodes, {} leaves, {} primitives",
+            "BVH",
+            self.bvh.trees,
+            si(self.bvh.nodes as f64),
+            si(self.bvh.leaves as f64),
+            si(self.bvh.primitives as f64)
+        );
+        println!("  {:<20} {}", "BVH max depth", self.bvh.max_depth);
+        println!("  {:<20} {:.2}", "BVH SAH cost", self.bvh.sah_cost);
+        println!(
+            "  {:<20} {:.2} s",
+            "BVH build time",
+            self.bvh.build_time.as_secs_f64()
+        );
+        for (thread, busy) in self.busy.iter().enumerate() {
+            println!(
+                "  {:<20} busy {:.2} s, idle {:.2} s",
+                format!("Thread {}", thread),
+                busy.as_secs_f64(),
+                self.idle(*busy).as_secs_f64()
+            );
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut report = json!({
+            "render_seconds": self.elapsed.as_secs_f64(),
+            "rays_per_second": self.rays_per_second(),
+            "threads": self.busy.iter().map(|busy| json!({
+                "busy_seconds": busy.as_secs_f64(),
+                "idle_seconds": self.idle(*busy).as_secs_f64(),
+            })).collect::<Vec<_>>(),
+            "bvh": {
+                "trees": self.bvh.trees,
+                "nodes": self.bvh.nodes,
+                "leaves": self.bvh.leaves,
+                "primitives": self.bvh.primitives,
+                "max_depth": self.bvh.max_depth,
+                "sah_cost": self.bvh.sah_cost,
+                "build_seconds": self.bvh.build_time.as_secs_f64(),
+            },
+        });
+        for ((key, _), count) in NAMES.iter().zip(self.counts) {
+            report[key] = json!(count);
+        }
+        report
+    }
+}
+
+// 带国际单位制词头的数值 如 1.23 M
+fn si(value: f64) -> String {
+    const PREFIXES: [&str; 5] = ["", "k", "M", "G", "T"];
+    let mut value = value;
+    let mut k = 0;
+    while value >= 1000.0 && k + 1 < PREFIXES.len() {
+        value /= 1000.0;
+        k += 1;
+    }
+    if k == 0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2} {}", value, PREFIXES[k])
+    }
+}
@@ -1,17 +1,27 @@
 // 纹理
 
 pub mod perlin;
+use crate::color::{self, ColorSpace};
 use crate::vec3::{Color, Vec3};
-use image::{GenericImageView, ImageBuffer, Rgb};
 use perlin::Perlin;
 use radiant::RGB;
+use rand::rngs::StdRng;
+use std::error::Error;
 use std::io::BufReader;
 use std::path::Path;
+use std::sync::Arc;
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: Vec3) -> Color;
 }
 
+// 运行时确定的纹理 如场景文件
+impl Texture for Arc<dyn Texture> {
+    fn value(&self, u: f64, v: f64, p: Vec3) -> Color {
+        self.as_ref().value(u, v, p)
+    }
+}
+
 // 纯色
 #[derive(Clone, Copy)]
 pub struct SolidColor {
@@ -20,7 +30,9 @@ pub struct SolidColor {
 
 impl SolidColor {
     pub fn new(color_value: Color) -> Self {
-        Self { color_value }
+        Self {
+            color_value: color::to_working(color_value),
+        }
     }
 }
 
@@ -61,9 +73,9 @@ pub struct NoiseTexture {
 }
 
 impl NoiseTexture {
-    pub fn new(scale: f64) -> Self {
+    pub fn new(scale: f64, rng: &mut StdRng) -> Self {
         Self {
-            noise: Perlin::new(),
+            noise: Perlin::new(rng),
             scale,
         }
     }
@@ -77,22 +89,48 @@ impl Texture for NoiseTexture {
 }
 
 // 贴图
+// 加载时解码为工作空间中的线性颜色
 pub struct ImageTexture {
-    data: ImageBuffer<Rgb<u8>, Vec<u8>>,
+    data: Vec<Color>,
     width: u32,
     height: u32,
 }
 
 impl ImageTexture {
+    // 8 位图片默认按 sRGB 编码
     pub fn new(file_name: String) -> Self {
-        let im = image::open(&Path::new(&file_name)).unwrap();
-        let dim = im.dimensions();
-        let data = im.into_rgb8();
-        Self {
+        Self::with_color_space(file_name, ColorSpace::Srgb)
+    }
+
+    pub fn with_color_space(file_name: String, color_space: ColorSpace) -> Self {
+        Self::open(&file_name, color_space).unwrap()
+    }
+
+    // 文件无法读取时返回错误
+    pub fn open(file_name: &str, color_space: ColorSpace) -> Result<Self, image::ImageError> {
+        let im = image::open(&Path::new(file_name))?.into_rgb8();
+        let decode: Vec<f64> = (0..=255u8)
+            .map(|v| match color_space {
+                ColorSpace::Srgb => color::srgb_eotf(v as f64 / 255.0),
+                ColorSpace::Linear => v as f64 / 255.0,
+            })
+            .collect();
+        let data = im
+            .pixels()
+            .map(|p| {
+                let [r, g, b] = p.0;
+                color::to_working(Color::new(
+                    decode[r as usize],
+                    decode[g as usize],
+                    decode[b as usize],
+                ))
+            })
+            .collect();
+        Ok(Self {
             data,
-            width: dim.0,
-            height: dim.1,
-        }
+            width: im.width(),
+            height: im.height(),
+        })
     }
 }
 
@@ -100,12 +138,7 @@ impl Texture for ImageTexture {
     fn value(&self, u: f64, v: f64, _: Vec3) -> Color {
         let i = ((u * self.width as f64) as u32).clamp(0, self.width - 1);
         let j = ((v * self.height as f64) as u32).clamp(0, self.height - 1);
-        let rgb = self.data[(i, j)].0;
-        Color::new(
-            (rgb[0] as f64) / 255.0,
-            (rgb[1] as f64) / 255.0,
-            (rgb[2] as f64) / 255.0,
-        )
+        self.data[(j * self.width + i) as usize]
     }
 }
 
@@ -114,23 +147,37 @@ pub struct HdrImageTexture {
     data: Vec<RGB>,
     width: u32,
     height: u32,
-    total: usize,
     limit: f64,
 }
 
 impl HdrImageTexture {
     pub fn new(file_name: String, limit: f64) -> Self {
-        let f = std::fs::File::open(&file_name).expect("Failed to open specified file");
-        let f = BufReader::new(f);
-        let image = radiant::load(f).expect("Failed to load image data");
-        let data = image.data;
-        Self {
+        Self::open(&file_name, limit).expect("Failed to load image data")
+    }
+
+    // 文件无法读取时返回错误
+    pub fn open(file_name: &str, limit: f64) -> Result<Self, Box<dyn Error>> {
+        let f = BufReader::new(std::fs::File::open(file_name)?);
+        let image = radiant::load(f)?;
+        // 转换到工作空间
+        let data = image
+            .data
+            .into_iter()
+            .map(|rgb| {
+                let c = color::to_working(Color::new(rgb.r as f64, rgb.g as f64, rgb.b as f64));
+                RGB {
+                    r: c.x as f32,
+                    g: c.y as f32,
+                    b: c.z as f32,
+                }
+            })
+            .collect();
+        Ok(Self {
             data,
             width: image.width as u32,
             height: image.height as u32,
-            total: image.width * image.height,
             limit,
-        }
+        })
     }
 }
 
@@ -146,11 +193,14 @@ impl Texture for HdrImageTexture {
         let v11 = di * dj;
         let i = (ii as u32).clamp(0, self.width - 1);
         let j = (jj as u32).clamp(0, self.height - 1);
-        let idx = (j * self.width + i) as usize;
-        let rgb00 = &self.data[idx];
-        let rgb10 = &self.data[(idx + 1).clamp(0, self.total)];
-        let rgb01 = &self.data[(idx + self.width as usize).clamp(0, self.total)];
-        let rgb11 = &self.data[(idx + self.width as usize + 1).clamp(0, self.total)];
+        // 邻居按轴分别夹在边缘 右边缘不会读到下一行开头
+        let i1 = (i + 1).min(self.width - 1);
+        let j1 = (j + 1).min(self.height - 1);
+        let at = |i: u32, j: u32| &self.data[(j * self.width + i) as usize];
+        let rgb00 = at(i, j);
+        let rgb10 = at(i1, j);
+        let rgb01 = at(i, j1);
+        let rgb11 = at(i1, j1);
         Color::new(
             (rgb00.r * v00 + rgb01.r * v01 + rgb10.r * v10 + rgb11.r * v11) as f64,
             (rgb00.g * v00 + rgb01.g * v01 + rgb10.g * v10 + rgb11.g * v11) as f64,
@@ -158,3 +208,27 @@ impl Texture for HdrImageTexture {
         ) * self.limit
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(r: f32) -> RGB {
+        RGB { r, g: r, b: r }
+    }
+
+    #[test]
+    fn hdr_lookup_does_not_wrap_at_the_right_edge() {
+        // 第一行为1 第二行为100
+        let texture = HdrImageTexture {
+            data: vec![rgb(1.0), rgb(1.0), rgb(100.0), rgb(100.0)],
+            width: 2,
+            height: 2,
+            limit: 1.0,
+        };
+        let c = texture.value(0.999, 0.0, Vec3::zero());
+        assert!((c.x - 1.0).abs() < 1e-6, "{:?}", c);
+        let c = texture.value(0.999, 0.999, Vec3::zero());
+        assert!((c.x - 100.0).abs() < 1e-3, "{:?}", c);
+    }
+}
@@ -1,6 +1,7 @@
 // 柏林噪声
 
 use crate::vec3::Vec3;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 use std::vec::Vec;
 
@@ -13,24 +14,23 @@ pub struct Perlin {
 }
 
 impl Perlin {
-    pub fn new() -> Self {
+    pub fn new(rng: &mut StdRng) -> Self {
         let point_count: usize = 256;
         let ranvec: Vec<Vec3> = (0..point_count)
-            .map(|_| Vec3::random_unit_vector())
+            .map(|_| Vec3::random_unit_vector(rng))
             .collect();
         Self {
             point_count,
             ranvec,
-            perm_x: Perlin::perlin_generate_perm(point_count),
-            perm_y: Perlin::perlin_generate_perm(point_count),
-            perm_z: Perlin::perlin_generate_perm(point_count),
+            perm_x: Perlin::perlin_generate_perm(point_count, rng),
+            perm_y: Perlin::perlin_generate_perm(point_count, rng),
+            perm_z: Perlin::perlin_generate_perm(point_count, rng),
         }
     }
 
-    fn perlin_generate_perm(len: usize) -> Vec<usize> {
+    fn perlin_generate_perm(len: usize, rng: &mut StdRng) -> Vec<usize> {
         let mut p: Vec<usize> = (0..len).collect();
-        let mut rng = rand::thread_rng();
-        p.shuffle(&mut rng);
+        p.shuffle(rng);
         p
     }
 
@@ -0,0 +1,80 @@
+// 色调映射
+// 线性辐射亮度依次经过白平衡 曝光 映射曲线与 sRGB 编码 得到 8 位颜色
+
+use crate::cli::ToneMapper;
+use crate::color::{self, Mat3};
+use crate::vec3::Color;
+
+pub struct ToneMapping {
+    pub white_balance: Option<Mat3>,
+    // 以 EV 计 每加 1 亮度加倍
+    pub exposure: f64,
+    pub operator: ToneMapper,
+}
+
+impl ToneMapping {
+    pub fn new(exposure: f64, white_balance: Option<f64>, operator: ToneMapper) -> Self {
+        Self {
+            white_balance: white_balance.map(color::white_balance),
+            exposure,
+            operator,
+        }
+    }
+
+    // 映射到 [0, 1] 的显示线性值
+    pub fn map(&self, mut c: Color) -> Color {
+        if let Some(m) = &self.white_balance {
+            c = color::mul(m, c);
+        }
+        c *= 2f64.powf(self.exposure);
+        // NaN 与负值一律当作黑色
+        let c = Color::new(c.x.max(0.0), c.y.max(0.0), c.z.max(0.0));
+        let c = match self.operator {
+            ToneMapper::Clamp => c,
+            ToneMapper::Reinhard => per_channel(c, |x| x / (1.0 + x)),
+            ToneMapper::Hable => {
+                let white = hable(11.2);
+                per_channel(c, |x| hable(2.0 * x) / white)
+            }
+            ToneMapper::Aces => aces_fitted(c),
+        };
+        per_channel(c, |x| x.clamp(0.0, 1.0))
+    }
+
+    pub fn to_u8(&self, c: Color) -> (u8, u8, u8) {
+        let c = per_channel(self.map(c), |x| (color::srgb_oetf(x) * 255.0).round());
+        (c.x as u8, c.y as u8, c.z as u8)
+    }
+}
+
+fn per_channel(c: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(c.x), f(c.y), f(c.z))
+}
+
+// Uncharted 2 的胶片曲线
+// 见 Hable, Filmic Tonemapping Operators, 2010
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+// ACES RRT 与 sRGB ODT 的拟合
+// 见 Hill, BakingLab, 2016
+const ACES_INPUT: Mat3 = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+
+const ACES_OUTPUT: Mat3 = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn aces_fitted(c: Color) -> Color {
+    let c = per_channel(color::mul(&ACES_INPUT, c), |v| {
+        (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951_0) + 0.238_081)
+    });
+    color::mul(&ACES_OUTPUT, c)
+}
@@ -43,19 +43,6 @@ impl Vec3 {
         self.length_squared().sqrt()
     }
 
-    pub fn to_u8(self) -> (u8, u8, u8) {
-        // 加入 gamma 矫正
-        let x = (self.x.sqrt().clamp(0.0, 0.999) * 256.0) as u8;
-        let y = (self.y.sqrt().clamp(0.0, 0.999) * 256.0) as u8;
-        let z = (self.z.sqrt().clamp(0.0, 0.999) * 256.0) as u8;
-        (x, y, z)
-    }
-
-    pub fn get_color_string(&self) -> String {
-        let xyz = self.to_u8();
-        format!("{} {} {}\n", xyz.0, xyz.1, xyz.2)
-    }
-
     pub fn dot(u: Vec3, v: Vec3) -> f64 {
         u.x * v.x + u.y * v.y + u.z * v.z
     }
@@ -72,13 +59,11 @@ impl Vec3 {
         v / v.length()
     }
 
-    pub fn random() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         Vec3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>())
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         let mut v: Vec3;
         loop {
             v = Vec3::new(
@@ -92,12 +77,12 @@ impl Vec3 {
         }
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        Vec3::unit_vector(Vec3::random_in_unit_sphere())
+    pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
+        Vec3::sphere_direction((rng.gen(), rng.gen()))
     }
 
-    pub fn random_in_hemisphere(normal: Vec3) -> Vec3 {
-        let in_unit_sphere = Vec3::random_in_unit_sphere();
+    pub fn random_in_hemisphere<R: Rng + ?Sized>(normal: Vec3, rng: &mut R) -> Vec3 {
+        let in_unit_sphere = Vec3::random_in_unit_sphere(rng);
         if Vec3::dot(in_unit_sphere, normal) > 0.0 {
             in_unit_sphere
         } else {
@@ -105,8 +90,7 @@ impl Vec3 {
         }
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn random_in_unit_disk<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         let mut v: Vec3;
         loop {
             v = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
@@ -116,6 +100,48 @@ impl Vec3 {
         }
     }
 
+    // 以下由 [0, 1) 上的二维样本生成 便于使用分层或低差异的样本
+
+    // 单位球面上均匀分布的方向
+    pub fn sphere_direction((r1, r2): (f64, f64)) -> Vec3 {
+        let z = 1.0 - 2.0 * r1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * r2;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    // 单位圆盘上均匀分布的点 同心映射保持样本的分层
+    pub fn concentric_disk((r1, r2): (f64, f64)) -> Vec3 {
+        let (a, b) = (2.0 * r1 - 1.0, 2.0 * r2 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::zero();
+        }
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, std::f64::consts::FRAC_PI_4 * (b / a))
+        } else {
+            (
+                b,
+                std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b),
+            )
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+
+    // 按余弦分布生成 z 轴半球上的方向
+    pub fn cosine_direction((r1, r2): (f64, f64)) -> Vec3 {
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let z = (1.0 - r2).sqrt();
+        Vec3::new(phi.cos() * r2.sqrt(), phi.sin() * r2.sqrt(), z)
+    }
+
+    // 生成指向 z 轴方向上某个球体的方向
+    pub fn to_sphere_direction(radius: f64, distance_squared: f64, (r1, r2): (f64, f64)) -> Vec3 {
+        let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let sin_theta = (1.0 - z * z).sqrt();
+        Vec3::new(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+    }
+
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
         self.x.abs() < s && self.y.abs() < s && self.z.abs() < s